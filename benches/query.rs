@@ -0,0 +1,248 @@
+//! Benchmarks of [`Query`](kn::Query) over synthetic trees, of
+//! [`Abbr::compare`](kn::Abbr::compare) and of queries using the index.
+
+use std::{
+    fs,
+    hint::black_box,
+    path::Path,
+    process::Command,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kn::{Abbr, Entry, Lister, MemoryTree, Query, QueryOptions};
+
+/// The number of dirs on each level of the synthetic tree.
+const WIDTH: usize = 32;
+
+/// The number of levels of the synthetic tree.
+const DEPTH: usize = 3;
+
+/// Creates `WIDTH` dirs with `DEPTH` levels of subdirs in `path`.
+fn create_tree(path: &Path, depth: usize) {
+    if depth == 0 {
+        return;
+    }
+
+    for i in 0..WIDTH {
+        let child = path.join(format!("dir-{}", i));
+        fs::create_dir(&child).unwrap();
+        create_tree(&child, depth - 1);
+    }
+}
+
+/// Creates the same tree as [`create_tree`](create_tree) in memory.
+fn create_memory_tree(
+    tree: MemoryTree,
+    path: &Path,
+    depth: usize,
+) -> MemoryTree {
+    if depth == 0 {
+        return tree.dir(path);
+    }
+
+    (0..WIDTH).fold(tree, |tree, i| {
+        create_memory_tree(tree, &path.join(format!("dir-{}", i)), depth - 1)
+    })
+}
+
+/// The time it takes [`SlowLister`](SlowLister) to list a dir.
+const LATENCY: Duration = Duration::from_micros(100);
+
+/// Lists dirs kept in memory after a delay, like a network filesystem.
+struct SlowLister(MemoryTree);
+
+impl Lister for SlowLister {
+    fn list(&self, dir: &Path) -> Vec<Entry> {
+        thread::sleep(LATENCY);
+
+        self.0.list(dir)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.0.is_dir(path)
+    }
+}
+
+/// The number of dirs in the wide synthetic tree.
+const WIDE_WIDTH: usize = 20_000;
+
+/// Creates `WIDE_WIDTH` dirs with varied names in memory, each containing a
+/// single subdir.
+fn create_wide_memory_tree() -> MemoryTree {
+    (0..WIDE_WIDTH).fold(MemoryTree::new(), |tree, i| {
+        tree.dir(format!("/Some-Project_{}-v{}/src", i, i % 7))
+    })
+}
+
+/// Searches the whole tree in `root` in the process and checks that it has
+/// found a path.
+fn search(root: &Path, abbr: &str, options: QueryOptions) {
+    let options = options.start_dir(root);
+    let matches = Query::with_options(abbr, options).matches().unwrap();
+
+    assert!(matches.len() > 0);
+}
+
+fn bench_deep_tree(c: &mut Criterion) {
+    let root = tempfile::tempdir().unwrap();
+    create_tree(root.path(), DEPTH);
+
+    let memory_tree =
+        create_memory_tree(MemoryTree::new(), Path::new("/"), DEPTH);
+    let mut group = c.benchmark_group("deep");
+
+    for abbr in ["DIR-1/DIR-1/DIR-1", "-/dir-1/dir-1", "d1/d1/d1"] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(abbr),
+            &abbr,
+            |b, &abbr| {
+                let options = QueryOptions::new()
+                    .threads(1)
+                    .lister(Arc::new(memory_tree.clone()));
+                b.iter(|| search(Path::new("/"), abbr, options.clone()))
+            },
+        );
+    }
+
+    group.finish();
+
+    // Every matching dir is read. Reading the disk scales with the number of
+    // cores, waiting for a slow filesystem scales even on a single one.
+    let slow_lister: Arc<dyn Lister> = Arc::new(SlowLister(memory_tree));
+    let mut group = c.benchmark_group("threads");
+
+    for threads in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("disk", threads),
+            &threads,
+            |b, &threads| {
+                let options = QueryOptions::new().threads(threads);
+                b.iter(|| search(root.path(), "d/d/d", options.clone()))
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("slow", threads),
+            &threads,
+            |b, &threads| {
+                let options = QueryOptions::new()
+                    .threads(threads)
+                    .lister(Arc::clone(&slow_lister));
+                b.iter(|| search(Path::new("/"), "d/d/d", options.clone()))
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_abbrs(c: &mut Criterion) {
+    let lister: Arc<dyn Lister> = Arc::new(create_wide_memory_tree());
+    let mut group = c.benchmark_group("abbrs");
+
+    for abbr in ["some-project_123-v4/src", "spv/src", "prj/src", "-/src"] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(abbr),
+            &abbr,
+            |b, &abbr| {
+                let options =
+                    QueryOptions::new().threads(1).lister(Arc::clone(&lister));
+                b.iter(|| search(Path::new("/"), abbr, options.clone()))
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_compare(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compare");
+
+    // Each pair takes a different path: a prefix, word boundaries, a
+    // subsequence and a mismatch.
+    for (abbr, component) in [
+        ("some", "Some-Project_123-v4"),
+        ("spv", "Some-Project_123-v4"),
+        ("prj", "Some-Project_123-v4"),
+        ("xyz", "Some-Project_123-v4"),
+    ] {
+        let abbr_parsed = Abbr::parse(abbr).unwrap();
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(abbr),
+            &component,
+            |b, &component| {
+                b.iter(|| abbr_parsed.compare(black_box(component)))
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Creates a `_kn` command run in `root` with the cache dir, the runtime dir
+/// and the config file in `env`, so that no daemon and no user's config are
+/// used.
+fn kn(root: &Path, env: &Path) -> Command {
+    let config = env.join("config.toml");
+    if !config.exists() {
+        fs::write(&config, "").unwrap();
+    }
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE__kn"));
+    command
+        .current_dir(root)
+        .env("XDG_CACHE_HOME", env.join("cache"))
+        .env("XDG_RUNTIME_DIR", env.join("runtime"))
+        .env("KN_CONFIG", config);
+
+    command
+}
+
+/// Runs the query in `root` and checks that it has found a path.
+fn query(root: &Path, env: &Path, index: bool) {
+    let mut command = kn(root, env);
+    command.args(["query", "--abbr", "d/d/d"]);
+    if index {
+        command.arg("--index");
+    }
+
+    let output = command.output().unwrap();
+    assert!(output.status.success() && !output.stdout.is_empty());
+}
+
+// The index is read by the `_kn` binary, so queries are run in processes.
+fn bench_index(c: &mut Criterion) {
+    let root = tempfile::tempdir().unwrap();
+    create_tree(root.path(), DEPTH);
+
+    let env = tempfile::tempdir().unwrap();
+    let status = kn(root.path(), env.path())
+        .args(["index", "build", "."])
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+
+    let mut group = c.benchmark_group("index");
+
+    // Without the index, the dirs are listed with `DirReader`.
+    for (name, index) in [("disk", false), ("index", true)] {
+        group.bench_function(name, |b| {
+            b.iter(|| query(root.path(), env.path(), index))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_deep_tree,
+    bench_abbrs,
+    bench_compare,
+    bench_index
+);
+criterion_main!(benches);
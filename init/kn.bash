@@ -15,7 +15,15 @@ function kn() {{
     else
         # otherwise, query _kn
 
-        \builtin local __kn_result
-        __kn_result="$({query_command})" && \builtin cd "${{__kn_result}}"
+        \builtin local __kn_result __kn_arg
+        \builtin local -a __kn_args
+
+        for __kn_arg in "$@"; do
+            __kn_args+=(--abbr "${{__kn_arg}}")
+        done
+
+        __kn_result="$({query_command})" || \builtin return
+
+        \builtin cd "${{__kn_result}}"
     fi
 }}
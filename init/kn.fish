@@ -17,9 +17,16 @@ function kn
     else
         # otherwise, query _kn
 
+        set -l __kn_args
+
+        for __kn_arg in $argv
+            set -a __kn_args --abbr $__kn_arg
+        end
+
         set -l __kn_result (command {query_command})
+        or return
 
-        and if test -d "$__kn_result"
+        if test -d "$__kn_result"
             cd "$__kn_result"
         end
     end
@@ -2,7 +2,14 @@
 
 use std::cmp::{Ord, Ordering};
 
+use glob::{MatchOptions, Pattern};
 use powierza_coefficient::powierża_coefficient;
+use regex::{Regex, RegexBuilder};
+
+use crate::{
+    error::Error,
+    matcher::{osa_distances, DefaultMatcher, Matcher, Scratch},
+};
 
 /// A component of the user's query.
 ///
@@ -11,18 +18,68 @@ use powierza_coefficient::powierża_coefficient;
 #[derive(Debug, Clone)]
 pub enum Abbr {
     /// Wildcard matches every component with congruence
-    /// [`Complete`](Congruence::Complete).
+    /// [`Complete`](Congruence::Complete) (or the
+    /// [best score](Matcher::best_score) of another matcher).
     Wildcard,
 
     /// Literal abbreviation.
     Literal(String),
+
+    /// Abbreviation matching only the component equal to it, e.g. `=src`.
+    Exact(String),
+
+    /// Abbreviation matching only components beginning with it, e.g. `src^`.
+    Prefix(String),
+
+    /// Abbreviation matching only components ending with it, e.g. `$test`.
+    Suffix(String),
+
+    /// Glob pattern, e.g. `v[0-9]*`. Matches components with the
+    /// [best score](Matcher::best_score), ignoring the case.
+    Glob(Pattern),
+
+    /// Regular expression, written in curly braces, e.g. `{^v\d+$}`. Matches
+    /// components containing a match of the regex with the
+    /// [best score](Matcher::best_score), ignoring the case.
+    Regex(Regex),
+
+    /// Abbreviation with negated parts, e.g. `api!old`. Matches components
+    /// matching the positive part unless any of the negated parts
+    /// [rejects](Abbr::rejects) them.
+    Negated {
+        /// The positive part.
+        positive: Box<Abbr>,
+
+        /// The negated parts.
+        negated: Vec<Abbr>,
+    },
+
+    /// Abbreviation consisting of space-separated tokens, e.g. `svc auth`.
+    /// Matches components matching every token, in any order, with the worst
+    /// of the tokens' scores.
+    Tokens(Vec<Abbr>),
 }
 
+/// A character of an abbreviation and whether it was escaped with `\`.
+type AbbrChar = (char, bool);
+
+/// Glob's special characters.
+const GLOB_CHARS: [char; 3] = ['*', '?', '['];
+
+/// Separates the positive part of an abbreviation from the negated parts.
+const NEGATION_CHAR: char = '!';
+
+/// Separates tokens of an abbreviation.
+const TOKEN_SEPARATOR: char = ' ';
+
 impl Abbr {
     /// Constructs [`Abbr::Wildcard`](Abbr::Wildcard) if the
     /// string slice is '-', otherwise constructs
     /// wrapped [`Abbr::Literal`](Abbr::Literal) with the abbreviation
     /// mapped to its ASCII lowercase equivalent.
+    ///
+    /// Unlike [`Abbr::parse`](Abbr::parse), it does not interpret patterns and
+    /// escapes.
     pub fn new_sanitized(abbr: &str) -> Self {
         if abbr == "-" {
             Self::Wildcard
@@ -31,26 +88,556 @@ impl Abbr {
         }
     }
 
-    /// Compares a component against the abbreviation.
+    /// Parses a component of the user's query.
+    ///
+    /// - `-` is parsed into [`Abbr::Wildcard`](Abbr::Wildcard).
+    /// - A component wrapped in curly braces is parsed into
+    ///   [`Abbr::Regex`](Abbr::Regex).
+    /// - A component containing `*`, `?` or `[` is parsed into
+    ///   [`Abbr::Glob`](Abbr::Glob).
+    /// - Any other component is parsed into [`Abbr::Literal`](Abbr::Literal).
+    ///
+    /// Each of the above may be followed by negated parts, each preceded by
+    /// `!`, e.g. `api!old!legacy`. Such component is parsed into
+    /// [`Abbr::Negated`](Abbr::Negated). If the positive part is empty, it is
+    /// parsed into [`Abbr::Wildcard`](Abbr::Wildcard).
+    ///
+    /// A component consisting of many space-separated tokens, e.g. `svc auth`,
+    /// is parsed into [`Abbr::Tokens`](Abbr::Tokens). Each token is parsed as
+    /// described above.
+    ///
+    /// A backslash makes the following character literal, so `\-` matches
+    /// dir names containing a dash and `\*` matches dir names containing an
+    /// asterisk.
+    pub fn parse(abbr: &str) -> Result<Self, Error> {
+        let chars = unescape(abbr);
+        let tokens = split_chars(&chars, TOKEN_SEPARATOR)
+            .into_iter()
+            .filter(|token| !token.is_empty())
+            .collect::<Vec<_>>();
+
+        match tokens.as_slice() {
+            [] => Self::from_negation_chars(&chars),
+            [token] => Self::from_negation_chars(token),
+            tokens => tokens
+                .iter()
+                .map(|token| Self::from_negation_chars(token))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Self::Tokens),
+        }
+    }
+
+    /// Parses a component of the user's query which may contain negated parts
+    /// after unescaping.
+    fn from_negation_chars(chars: &[AbbrChar]) -> Result<Self, Error> {
+        let parts = split_chars(chars, NEGATION_CHAR);
+        let (positive, negated) = match parts.split_first() {
+            Some((positive, negated)) if !negated.is_empty() =>
+                (positive, negated),
+            _ => return Self::from_chars(chars),
+        };
+
+        let positive = if positive.is_empty() {
+            Self::Wildcard
+        } else {
+            Self::from_chars(positive)?
+        };
+        let negated = negated
+            .iter()
+            .map(|negated| {
+                if negated.is_empty() {
+                    Err(Error::InvalidPattern(format!(
+                        "`{}` contains an empty negation.",
+                        escape(chars)
+                    )))
+                } else {
+                    Self::from_chars(negated)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self::Negated {
+            positive: Box::new(positive),
+            negated,
+        })
+    }
+
+    /// Parses a component of the user's query after unescaping.
+    fn from_chars(chars: &[AbbrChar]) -> Result<Self, Error> {
+        match chars {
+            [('-', false)] => Ok(Self::Wildcard),
+            [('{', false), inner @ .., ('}', false)] => {
+                let regex = RegexBuilder::new(&escape(inner))
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|error| {
+                        Error::InvalidPattern(error.to_string())
+                    })?;
+
+                Ok(Self::Regex(regex))
+            }
+            [('=', false), rest @ ..] if !rest.is_empty() =>
+                Ok(Self::Exact(parse_anchored_literal(rest)?)),
+            [rest @ .., ('^', false)] if !rest.is_empty() =>
+                Ok(Self::Prefix(parse_anchored_literal(rest)?)),
+            [('$', false), rest @ ..] if !rest.is_empty() =>
+                Ok(Self::Suffix(parse_anchored_literal(rest)?)),
+            _ if chars
+                .iter()
+                .any(|(c, escaped)| !escaped && GLOB_CHARS.contains(c)) =>
+            {
+                let pattern = chars
+                    .iter()
+                    .map(|&(c, escaped)| {
+                        if escaped {
+                            Pattern::escape(&c.to_string())
+                        } else {
+                            c.to_string()
+                        }
+                    })
+                    .collect::<String>();
+                let pattern = Pattern::new(&pattern).map_err(|error| {
+                    Error::InvalidPattern(error.to_string())
+                })?;
+
+                Ok(Self::Glob(pattern))
+            }
+            _ => Ok(Self::Literal(
+                chars.iter().map(|(c, _)| c.to_ascii_lowercase()).collect(),
+            )),
+        }
+    }
+
+    /// Compares a component against the abbreviation using the
+    /// [`DefaultMatcher`](DefaultMatcher).
+    ///
+    /// If the abbreviation is a subsequence of the component, it is checked
+    /// whether each run of its consecutive characters begins a word of the
+    /// component. Words are separated by `-`, `_`, `.` and spaces, camelCase
+    /// humps and runs of digits, so `fbs` matches `foo-bar-service` with
+    /// congruence [`Boundary`](Congruence::Boundary) and `fabulous` with
+    /// congruence [`Subsequence`](Congruence::Subsequence).
     pub fn compare(&self, component: &str) -> Option<Congruence> {
-        // What about characters with accents? [https://eev.ee/blog/2015/09/12/dark-corners-of-unicode/]
-        let component = component.to_ascii_lowercase();
+        self.compare_with(&DefaultMatcher::default(), component)
+    }
 
+    /// Compares a component against the abbreviation using the provided
+    /// [`Matcher`](Matcher).
+    pub fn compare_with<M>(
+        &self,
+        matcher: &M,
+        component: &str,
+    ) -> Option<M::Score>
+    where
+        M: Matcher,
+    {
         match self {
-            Self::Wildcard => Some(Congruence::Complete),
+            Self::Wildcard => Some(matcher.best_score()),
             Self::Literal(literal) =>
-                if literal.is_empty() || component.is_empty() {
+                matcher.compare_literal(literal, component),
+            Self::Exact(literal) =>
+                if component.eq_ignore_ascii_case(literal) {
+                    matcher.compare_literal(literal, component)
+                } else {
+                    None
+                },
+            Self::Prefix(literal) =>
+                if starts_with_ignore_ascii_case(component, literal) {
+                    matcher.compare_literal(literal, component)
+                } else {
                     None
-                } else if *literal == component {
-                    Some(Congruence::Complete)
-                } else if component.starts_with(literal) {
-                    Some(Congruence::Prefix)
+                },
+            Self::Suffix(literal) =>
+                if ends_with_ignore_ascii_case(component, literal) {
+                    matcher.compare_literal(literal, component)
                 } else {
-                    powierża_coefficient(literal, &component)
-                        .map(Congruence::Subsequence)
+                    None
                 },
+            Self::Glob(pattern) => {
+                let options = MatchOptions {
+                    case_sensitive: false,
+                    require_literal_separator: false,
+                    require_literal_leading_dot: false,
+                };
+
+                if pattern.matches_with(component, options) {
+                    Some(matcher.best_score())
+                } else {
+                    None
+                }
+            }
+            Self::Regex(regex) =>
+                if regex.is_match(component) {
+                    Some(matcher.best_score())
+                } else {
+                    None
+                },
+            Self::Negated { positive, negated } =>
+                if negated.iter().any(|negated| negated.rejects(component)) {
+                    None
+                } else {
+                    positive.compare_with(matcher, component)
+                },
+            Self::Tokens(tokens) => tokens
+                .iter()
+                .try_fold(None, |worst, token| {
+                    let score = token.compare_with(matcher, component)?;
+
+                    Some(worst.max(Some(score)))
+                })
+                .flatten(),
+        }
+    }
+
+    /// Returns whether the component is rejected by the abbreviation when
+    /// it is negated.
+    ///
+    /// A negated literal rejects components containing it, ignoring the case.
+    /// Other abbreviations reject components they match.
+    pub fn rejects(&self, component: &str) -> bool {
+        match self {
+            Self::Literal(literal) =>
+                contains_ignore_ascii_case(component, literal),
+            _ => self.compare(component).is_some(),
+        }
+    }
+}
+
+/// Parses the part of an anchored abbreviation following or preceding the
+/// anchor. It must be a literal.
+fn parse_anchored_literal(chars: &[AbbrChar]) -> Result<String, Error> {
+    match Abbr::from_chars(chars)? {
+        Abbr::Literal(literal) => Ok(literal),
+        _ => Err(Error::InvalidPattern(format!(
+            "`{}` can't be anchored, only literals can.",
+            escape(chars)
+        ))),
+    }
+}
+
+/// Splits the abbreviation on the unescaped separator. Separators inside curly
+/// braces are ignored so that they can be used in regexes.
+fn split_chars(chars: &[AbbrChar], separator: char) -> Vec<&[AbbrChar]> {
+    let mut parts = vec![];
+    let mut part_start = 0;
+    let mut depth = 0_usize;
+
+    for (ix, &(c, escaped)) in chars.iter().enumerate() {
+        match (c, escaped) {
+            ('{', false) => depth += 1,
+            ('}', false) => depth = depth.saturating_sub(1),
+            (c, false) if c == separator && depth == 0 => {
+                parts.push(&chars[part_start..ix]);
+                part_start = ix + 1;
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&chars[part_start..]);
+
+    parts
+}
+
+/// Splits the abbreviation into components on unescaped path separators.
+/// Separators inside curly braces are ignored so that they can be used in
+/// regexes, e.g. `{^a/b}`.
+pub(crate) fn split_components(abbr: &str) -> Vec<&str> {
+    let mut components = vec![];
+    let mut component_start = 0;
+    let mut depth = 0_usize;
+    let mut chars = abbr.char_indices();
+
+    while let Some((ix, c)) = chars.next() {
+        match c {
+            // On Windows, backslashes are separators.
+            '\\' if !std::path::is_separator(c) => {
+                let _escaped = chars.next();
+            }
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            c if std::path::is_separator(c) && depth == 0 => {
+                components.push(&abbr[component_start..ix]);
+                component_start = ix + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+
+    components.push(&abbr[component_start..]);
+
+    components
+}
+
+/// Splits the abbreviation into characters and marks those escaped with a
+/// backslash. A trailing backslash is treated as a literal character.
+fn unescape(abbr: &str) -> Vec<AbbrChar> {
+    let mut chars = abbr.chars();
+    let mut unescaped = vec![];
+
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(escaped)) => {
+                unescaped.push((escaped, true));
+                let _consumed = chars.next();
+            }
+            _ => unescaped.push((c, false)),
+        }
+    }
+
+    unescaped
+}
+
+/// Reverses [`unescape`](unescape).
+fn escape(chars: &[AbbrChar]) -> String {
+    chars
+        .iter()
+        .map(|&(c, escaped)| {
+            if escaped {
+                format!("\\{}", c)
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Compares a component against a literal, lowercase abbreviation. Used by
+/// [`DefaultMatcher`](DefaultMatcher).
+///
+/// If `typos` is `true` and the abbreviation is not a subsequence of the
+/// component, the component is matched with congruence
+/// [`Typo`](Congruence::Typo) if it begins with a string within a small edit
+/// distance from the abbreviation.
+pub(crate) fn compare_literal(
+    literal: &str,
+    component: &str,
+    typos: bool,
+) -> Option<Congruence> {
+    if literal.is_empty() || component.is_empty() {
+        return None;
+    }
+
+    if component.eq_ignore_ascii_case(literal) {
+        return Some(Congruence::Complete);
+    }
+
+    if starts_with_ignore_ascii_case(component, literal) {
+        let remainder = &component[literal.len()..];
+
+        return Some(Congruence::Prefix(remainder.chars().count() as u32));
+    }
+
+    // Most components don't match at all. Rule them out before lowercasing.
+    if !typos && !is_subsequence_ignore_ascii_case(literal, component) {
+        return None;
+    }
+
+    Scratch::with(|scratch| {
+        // What about characters with accents? [https://eev.ee/blog/2015/09/12/dark-corners-of-unicode/]
+        scratch.set_component(component);
+
+        if let Some(coefficient) =
+            powierża_coefficient(literal, &scratch.lowercase)
+        {
+            // Word boundaries depend on the case of the characters.
+            find_word_boundaries(component, &mut scratch.boundaries);
+
+            let Scratch {
+                chars,
+                boundaries,
+                gaps: [previous_row, current_row],
+                ..
+            } = scratch;
+
+            match count_boundary_gaps(
+                literal,
+                chars,
+                boundaries,
+                previous_row,
+                current_row,
+            ) {
+                Some(n_gaps) => Some(Congruence::Boundary(n_gaps)),
+                None => Some(Congruence::Subsequence(coefficient)),
+            }
+        } else if typos {
+            count_typos(literal, &scratch.chars, &mut scratch.distances)
+                .map(Congruence::Typo)
+        } else {
+            None
+        }
+    })
+}
+
+/// Checks if the component begins with the lowercase literal, ignoring the
+/// ASCII case of the component.
+fn starts_with_ignore_ascii_case(component: &str, literal: &str) -> bool {
+    component
+        .as_bytes()
+        .get(..literal.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(literal.as_bytes()))
+}
+
+/// Checks if the component ends with the lowercase literal, ignoring the ASCII
+/// case of the component.
+fn ends_with_ignore_ascii_case(component: &str, literal: &str) -> bool {
+    component
+        .len()
+        .checked_sub(literal.len())
+        .and_then(|suffix_start| component.as_bytes().get(suffix_start..))
+        .is_some_and(|suffix| suffix.eq_ignore_ascii_case(literal.as_bytes()))
+}
+
+/// Checks if the component contains the lowercase literal, ignoring the ASCII
+/// case of the component.
+fn contains_ignore_ascii_case(component: &str, literal: &str) -> bool {
+    literal.is_empty()
+        || component
+            .as_bytes()
+            .windows(literal.len())
+            .any(|window| window.eq_ignore_ascii_case(literal.as_bytes()))
+}
+
+/// Checks if the lowercase literal is a subsequence of the component, ignoring
+/// the ASCII case of the component.
+fn is_subsequence_ignore_ascii_case(literal: &str, component: &str) -> bool {
+    let mut component_chars = component.chars().map(|c| c.to_ascii_lowercase());
+
+    literal
+        .chars()
+        .all(|literal_char| component_chars.any(|c| c == literal_char))
+}
+
+/// The minimal length of an abbreviation for which typos are tolerated.
+const MIN_TYPO_ABBR_LEN: usize = 3;
+
+/// The length of an abbreviation from which two typos are tolerated instead of
+/// one.
+const TWO_TYPOS_ABBR_LEN: usize = 8;
+
+/// Counts the edits (insertions, deletions, substitutions and transpositions)
+/// needed to turn the abbreviation into a prefix of the component.
+///
+/// Returns [`None`] if the abbreviation is too short or there are more typos
+/// than tolerated. Both the abbreviation and the component's characters are
+/// expected to be lowercase already. The rows of the matrix are kept in
+/// `rows`.
+fn count_typos(
+    abbr: &str,
+    component: &[char],
+    rows: &mut [Vec<u32>; 3],
+) -> Option<u32> {
+    let abbr_len = abbr.chars().count();
+    let max_typos = if abbr_len < MIN_TYPO_ABBR_LEN {
+        return None;
+    } else if abbr_len < TWO_TYPOS_ABBR_LEN {
+        1
+    } else {
+        2
+    };
+
+    osa_distances(abbr, component, rows)
+        .iter()
+        .copied()
+        .min()
+        .filter(|n_typos| *n_typos <= max_typos)
+}
+
+/// Returns whether each of the component's characters begins a word.
+///
+/// A word begins at the start of the component, after a separator (`-`, `_`,
+/// `.` or a space), at a camelCase hump and where a run of digits begins or
+/// ends. For example, the words of `fooBar-baz2021` are `foo`, `Bar`, `baz` and
+/// `2021`. The result is stored in `boundaries`.
+pub(crate) fn find_word_boundaries(
+    component: &str,
+    boundaries: &mut Vec<bool>,
+) {
+    let is_separator = |c: char| matches!(c, '-' | '_' | '.' | ' ');
+    let mut chars = component.chars().peekable();
+    let mut previous = None;
+
+    boundaries.clear();
+
+    while let Some(current) = chars.next() {
+        let next = chars.peek().copied();
+        let is_boundary = match previous {
+            _ if is_separator(current) => false,
+            None => true,
+            Some(previous) =>
+                is_separator(previous)
+                    || previous.is_ascii_digit() != current.is_ascii_digit()
+                    || (previous.is_lowercase() && current.is_uppercase())
+                    || (previous.is_uppercase()
+                        && current.is_uppercase()
+                        && next.is_some_and(char::is_lowercase)),
+        };
+
+        boundaries.push(is_boundary);
+        previous = Some(current);
+    }
+}
+
+/// Counts the gaps between runs of the abbreviation's characters in the
+/// component, given that each run begins a word.
+///
+/// Returns [`None`] if the abbreviation's characters cannot be matched that
+/// way. If there are many ways to match them, the lowest number of gaps is
+/// returned. Both the abbreviation and the component's characters are
+/// expected to be lowercase already. The rows are used as buffers.
+fn count_boundary_gaps(
+    abbr: &str,
+    component: &[char],
+    boundaries: &[bool],
+    previous_row: &mut Vec<Option<u32>>,
+    current_row: &mut Vec<Option<u32>>,
+) -> Option<u32> {
+    // `previous_row[x]` holds the lowest number of gaps needed to match the
+    // abbreviation's characters so far with the last one matched at `x`.
+    previous_row.clear();
+
+    for (y, abbr_char) in abbr.chars().enumerate() {
+        current_row.clear();
+        current_row.resize(component.len(), None);
+        // The best score of the previous row strictly before the current
+        // column, i.e. of the runs that can be followed by a gap.
+        let mut best_before: Option<u32> = None;
+
+        for (x, &component_char) in component.iter().enumerate() {
+            if component_char == abbr_char {
+                current_row[x] = if y == 0 {
+                    if boundaries[x] {
+                        Some(0)
+                    } else {
+                        None
+                    }
+                } else {
+                    let continued = x
+                        .checked_sub(1)
+                        .and_then(|previous_x| previous_row[previous_x]);
+                    let started = if boundaries[x] {
+                        best_before.map(|n_gaps| n_gaps + 1)
+                    } else {
+                        None
+                    };
+
+                    match (continued, started) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    }
+                };
+            }
+
+            if let Some(Some(score)) = previous_row.get(x) {
+                best_before =
+                    Some(best_before.map_or(*score, |best| best.min(*score)));
+            }
         }
+
+        std::mem::swap(previous_row, current_row);
     }
+
+    previous_row.iter().flatten().min().copied()
 }
 
 /// The strength of the match between an abbreviation and a component.
@@ -59,33 +646,64 @@ impl Abbr {
 /// way:
 ///
 /// 1. Components are first ordered based on how well they match the
-/// abbreviation — first [`Complete`](Congruence::Complete), then
-/// [`Prefix`](Congruence::Prefix), then
-/// [`Subsequence`](Congruence::Subsequence).
-/// 2. Components with congruence [`Subsequence`](Congruence::Subsequence) are
-/// ordered by their [Powierża coefficient](https://github.com/micouy/powierza-coefficient).
-/// 3. If the order of two components cannot be determined based on the above, [`alphanumeric_sort`](https://docs.rs/alphanumeric-sort) is used.
+///    abbreviation — first [`Complete`](Congruence::Complete), then
+///    [`Prefix`](Congruence::Prefix), then [`Boundary`](Congruence::Boundary),
+///    then [`Subsequence`](Congruence::Subsequence), then
+///    [`Typo`](Congruence::Typo). The tiers never overlap, e.g. every
+///    [`Prefix`](Congruence::Prefix) comes before every
+///    [`Boundary`](Congruence::Boundary), no matter the fields.
+///    [`Complete`](Congruence::Complete) is equivalent to a prefix with no
+///    remainder.
+/// 2. Components with congruence [`Prefix`](Congruence::Prefix) are ordered by
+///    the length of the remainder, so the closest match comes first. Components
+///    with congruence [`Boundary`](Congruence::Boundary) are ordered by the
+///    number of gaps between the matched runs of characters.
+/// 3. Components with congruence [`Subsequence`](Congruence::Subsequence) are
+///    ordered by their [Powierża coefficient](https://github.com/micouy/powierza-coefficient).
+/// 4. Components with congruence [`Typo`](Congruence::Typo) are ordered by the
+///    number of typos.
+/// 5. If the order of two components cannot be determined based on the above,
+///    [tiebreakers](crate::tiebreak::Tiebreak) and finally
+///    [`alphanumeric_sort`](https://docs.rs/alphanumeric-sort) are used.
 ///
 /// Below are the results of matching components against abbreviation `abc`:
 ///
 /// | Component   | Match strength                           |
 /// |-------------|------------------------------------------|
 /// | `abc`       | [`Complete`](Congruence::Complete)       |
-/// | `abc___`    | [`Prefix`](Congruence::Prefix)           |
-/// | `_a_b_c_`   | [`Subsequence`](Congruence::Subsequence) |
+/// | `abc_`      | [`Prefix(1)`](Congruence::Prefix)        |
+/// | `abc___`    | [`Prefix(3)`](Congruence::Prefix)        |
+/// | `ab_cd`     | [`Boundary`](Congruence::Boundary)       |
+/// | `_a_b_c_`   | [`Boundary`](Congruence::Boundary)       |
+/// | `xaxbxcx`   | [`Subsequence`](Congruence::Subsequence) |
+/// | `acb`       | [`Typo`](Congruence::Typo) (if enabled)  |
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Congruence {
     /// Either the abbreviation and the component are the same or the
     /// abbreviation is a wildcard.
     Complete,
 
-    /// The abbreviation is a prefix of the component.
-    Prefix,
+    /// The abbreviation is a prefix of the component. The field contains the
+    /// number of the component's characters following the prefix.
+    Prefix(u32),
+
+    /// The abbreviation's characters form a subsequence of the component's
+    /// characters and each run of consecutive matched characters begins a word
+    /// (see [`Abbr::compare`](Abbr::compare)). The field contains the number
+    /// of gaps between the runs.
+    Boundary(u32),
 
     /// The abbreviation's characters form a subsequence of the component's
     /// characters. The field contains the Powierża coefficient of the pair of
     /// strings.
     Subsequence(u32),
+
+    /// The abbreviation is not a subsequence of the component but a prefix of
+    /// the component is within a small edit distance from the abbreviation.
+    /// The field contains the number of edits. Only produced by
+    /// [`DefaultMatcher`](DefaultMatcher) with `typos` enabled and only used
+    /// when there are no other matches.
+    Typo(u32),
 }
 
 use Congruence::*;
@@ -102,16 +720,35 @@ impl Ord for Congruence {
 
         match (self, other) {
             (Complete, Complete) => Equal,
-            (Complete, Prefix) => Less,
+            (Complete, Prefix(_)) => Less,
+            (Complete, Boundary(_)) => Less,
             (Complete, Subsequence(_)) => Less,
+            (Complete, Typo(_)) => Less,
 
-            (Prefix, Complete) => Greater,
-            (Prefix, Prefix) => Equal,
-            (Prefix, Subsequence(_)) => Less,
+            (Prefix(_), Complete) => Greater,
+            (Prefix(remainder_a), Prefix(remainder_b)) =>
+                remainder_a.cmp(remainder_b),
+            (Prefix(_), Boundary(_)) => Less,
+            (Prefix(_), Subsequence(_)) => Less,
+            (Prefix(_), Typo(_)) => Less,
+
+            (Boundary(_), Complete) => Greater,
+            (Boundary(_), Prefix(_)) => Greater,
+            (Boundary(gaps_a), Boundary(gaps_b)) => gaps_a.cmp(gaps_b),
+            (Boundary(_), Subsequence(_)) => Less,
+            (Boundary(_), Typo(_)) => Less,
 
             (Subsequence(_), Complete) => Greater,
-            (Subsequence(_), Prefix) => Greater,
+            (Subsequence(_), Prefix(_)) => Greater,
+            (Subsequence(_), Boundary(_)) => Greater,
             (Subsequence(dist_a), Subsequence(dist_b)) => dist_a.cmp(dist_b),
+            (Subsequence(_), Typo(_)) => Less,
+
+            (Typo(_), Complete) => Greater,
+            (Typo(_), Prefix(_)) => Greater,
+            (Typo(_), Boundary(_)) => Greater,
+            (Typo(_), Subsequence(_)) => Greater,
+            (Typo(typos_a), Typo(typos_b)) => typos_a.cmp(typos_b),
         }
     }
 }
@@ -120,12 +757,21 @@ impl Ord for Congruence {
 mod test {
     use super::*;
 
+    use crate::matcher::ExactMatcher;
+
     #[test]
     fn test_congruence_ordering() {
-        assert!(Complete < Prefix);
+        assert!(Complete < Prefix(0));
+        assert!(Complete < Boundary(0));
         assert!(Complete < Subsequence(1));
-        assert!(Prefix < Subsequence(1));
+        assert!(Prefix(1) < Prefix(1000));
+        assert!(Prefix(1000) < Boundary(0));
+        assert!(Prefix(1000) < Subsequence(0));
+        assert!(Boundary(1) < Boundary(1000));
+        assert!(Boundary(1000) < Subsequence(0));
         assert!(Subsequence(1) < Subsequence(1000));
+        assert!(Subsequence(1000) < Typo(0));
+        assert!(Typo(1) < Typo(2));
     }
 
     #[test]
@@ -133,10 +779,13 @@ mod test {
         let abbr = Abbr::new_sanitized("abcjkl");
 
         assert_variant!(abbr.compare("abcjkl"), Some(Complete));
-        assert_variant!(abbr.compare("abcjkl_"), Some(Prefix));
-        assert_variant!(abbr.compare("_abcjkl"), Some(Subsequence(0)));
-        assert_variant!(abbr.compare("abc_jkl"), Some(Subsequence(1)));
-        assert_variant!(abbr.compare("abc____jkl"), Some(Subsequence(1)));
+        assert_variant!(abbr.compare("abcjkl_"), Some(Prefix(1)));
+        assert_variant!(abbr.compare("abcjkl___"), Some(Prefix(3)));
+        assert_variant!(abbr.compare("_abcjkl"), Some(Boundary(0)));
+        assert_variant!(abbr.compare("abc_jkl"), Some(Boundary(1)));
+        assert_variant!(abbr.compare("abc____jkl"), Some(Boundary(1)));
+        assert_variant!(abbr.compare("xabcjkl"), Some(Subsequence(0)));
+        assert_variant!(abbr.compare("abcxjkl"), Some(Subsequence(1)));
 
         assert_variant!(abbr.compare("xyz"), None);
         assert_variant!(abbr.compare(""), None);
@@ -147,9 +796,285 @@ mod test {
         let abbr = Abbr::new_sanitized("AbCjKl");
 
         assert_variant!(abbr.compare("aBcJkL"), Some(Complete));
-        assert_variant!(abbr.compare("AbcJkl_"), Some(Prefix));
-        assert_variant!(abbr.compare("_aBcjKl"), Some(Subsequence(0)));
-        assert_variant!(abbr.compare("abC_jkL"), Some(Subsequence(1)));
+        assert_variant!(abbr.compare("AbcJkl_"), Some(Prefix(1)));
+        assert_variant!(abbr.compare("_aBcjKl"), Some(Boundary(0)));
+        assert_variant!(abbr.compare("abC_jkL"), Some(Boundary(1)));
+    }
+
+    #[test]
+    fn test_ignore_ascii_case() {
+        assert!(starts_with_ignore_ascii_case("FooBar", "foo"));
+        assert!(!starts_with_ignore_ascii_case("Fo", "foo"));
+        assert!(starts_with_ignore_ascii_case("żółW", "żół"));
+
+        assert!(ends_with_ignore_ascii_case("FooBar", "bar"));
+        assert!(!ends_with_ignore_ascii_case("ar", "bar"));
+
+        assert!(contains_ignore_ascii_case("FooBarBaz", "barb"));
+        assert!(contains_ignore_ascii_case("Foo", ""));
+        assert!(!contains_ignore_ascii_case("Foo", "foox"));
+
+        assert!(is_subsequence_ignore_ascii_case("fbz", "FooBarBaz"));
+        assert!(!is_subsequence_ignore_ascii_case("zbf", "FooBarBaz"));
+    }
+
+    #[test]
+    fn test_find_word_boundaries() {
+        fn words(component: &str) -> Vec<String> {
+            let mut boundaries = vec![];
+            find_word_boundaries(component, &mut boundaries);
+            let mut words: Vec<String> = vec![];
+
+            for (c, is_boundary) in component.chars().zip(boundaries) {
+                match words.last_mut() {
+                    Some(word) if !is_boundary && c.is_alphanumeric() =>
+                        word.push(c),
+                    _ if is_boundary => words.push(c.to_string()),
+                    _ => {}
+                }
+            }
+
+            words
+        }
+
+        assert_eq!(
+            words("foo-bar_baz.qux quux"),
+            ["foo", "bar", "baz", "qux", "quux"]
+        );
+        assert_eq!(words("fooBarBaz"), ["foo", "Bar", "Baz"]);
+        assert_eq!(words("HTTPServer"), ["HTTP", "Server"]);
+        assert_eq!(words("photos2021raw"), ["photos", "2021", "raw"]);
+        assert_eq!(words("--foo"), ["foo"]);
+    }
+
+    #[test]
+    fn test_compare_abbr_word_boundaries() {
+        let abbr = Abbr::new_sanitized("fbs");
+
+        assert_variant!(abbr.compare("foo-bar-service"), Some(Boundary(2)));
+        assert_variant!(abbr.compare("fooBarService"), Some(Boundary(2)));
+        assert_variant!(abbr.compare("fabulous"), Some(Subsequence(_)));
+
+        let abbr = Abbr::new_sanitized("foobs");
+
+        assert_variant!(abbr.compare("foo-bar-service"), Some(Boundary(2)));
+        assert_variant!(abbr.compare("foob-service"), Some(Boundary(1)));
+
+        let abbr = Abbr::new_sanitized("ph21");
+
+        assert_variant!(abbr.compare("photos2021"), Some(Subsequence(_)));
+        assert_variant!(abbr.compare("photos_2_1"), Some(Boundary(2)));
+    }
+
+    #[test]
+    fn test_compare_abbr_typos() {
+        let matcher = DefaultMatcher { typos: true };
+
+        let abbr = Abbr::new_sanitized("cofnig");
+        assert_variant!(abbr.compare("config"), None);
+        assert_variant!(abbr.compare_with(&matcher, "config"), Some(Typo(1)));
+        assert_variant!(
+            abbr.compare_with(&matcher, "Configuration"),
+            Some(Typo(1))
+        );
+        assert_variant!(abbr.compare_with(&matcher, "cnfg"), None);
+
+        // Matches which are not typos are unaffected.
+        assert_variant!(abbr.compare_with(&matcher, "cofnig"), Some(Complete));
+
+        // Substituted and extra characters.
+        let abbr = Abbr::new_sanitized("cinfig");
+        assert_variant!(abbr.compare_with(&matcher, "config"), Some(Typo(1)));
+        let abbr = Abbr::new_sanitized("conffig");
+        assert_variant!(abbr.compare_with(&matcher, "config"), Some(Typo(1)));
+
+        // Two typos are tolerated in long abbreviations.
+        let abbr = Abbr::new_sanitized("dcoumnets");
+        assert_variant!(
+            abbr.compare_with(&matcher, "documents"),
+            Some(Typo(2))
+        );
+
+        // Typos are not tolerated in short abbreviations.
+        let abbr = Abbr::new_sanitized("ba");
+        assert_variant!(abbr.compare_with(&matcher, "ab"), None);
+    }
+
+    #[test]
+    fn test_parse_abbr() {
+        assert_variant!(Abbr::parse("-"), Ok(Abbr::Wildcard));
+        assert_variant!(Abbr::parse("-x"), Ok(Abbr::Literal(literal)) if literal == "-x");
+        assert_variant!(Abbr::parse("AbC"), Ok(Abbr::Literal(literal)) if literal == "abc");
+        assert_variant!(Abbr::parse("v[0-9]*"), Ok(Abbr::Glob(_)));
+        assert_variant!(Abbr::parse("v?"), Ok(Abbr::Glob(_)));
+        assert_variant!(Abbr::parse("{^v\\d+$}"), Ok(Abbr::Regex(_)));
+
+        assert_variant!(Abbr::parse("[a-"), Err(Error::InvalidPattern(_)));
+        assert_variant!(Abbr::parse("{(}"), Err(Error::InvalidPattern(_)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_split_components() {
+        assert_eq!(split_components("pr/kn"), ["pr", "kn"]);
+        assert_eq!(split_components("pr//kn/"), ["pr", "", "kn", ""]);
+        assert_eq!(split_components("{^a/b}/kn"), ["{^a/b}", "kn"]);
+        assert_eq!(split_components("\\{a/b}"), ["\\{a", "b}"]);
+        assert_eq!(split_components("a\\/b"), ["a\\/b"]);
+    }
+
+    #[test]
+    fn test_parse_abbr_escapes() {
+        assert_variant!(Abbr::parse("\\-"), Ok(Abbr::Literal(literal)) if literal == "-");
+        assert_variant!(Abbr::parse("a\\*"), Ok(Abbr::Literal(literal)) if literal == "a*");
+        assert_variant!(Abbr::parse("\\{a}"), Ok(Abbr::Literal(literal)) if literal == "{a}");
+        assert_variant!(Abbr::parse("a\\"), Ok(Abbr::Literal(literal)) if literal == "a\\");
+
+        let abbr = Abbr::parse("\\[*").unwrap();
+        assert_variant!(abbr.compare("[abc"), Some(Complete));
+        assert_variant!(abbr.compare("abc"), None);
+    }
+
+    #[test]
+    fn test_parse_anchored_abbr() {
+        assert_variant!(Abbr::parse("=Src"), Ok(Abbr::Exact(literal)) if literal == "src");
+        assert_variant!(Abbr::parse("src^"), Ok(Abbr::Prefix(literal)) if literal == "src");
+        assert_variant!(Abbr::parse("$test"), Ok(Abbr::Suffix(literal)) if literal == "test");
+
+        assert_variant!(Abbr::parse("="), Ok(Abbr::Literal(literal)) if literal == "=");
+        assert_variant!(Abbr::parse("^"), Ok(Abbr::Literal(literal)) if literal == "^");
+        assert_variant!(Abbr::parse("a=b"), Ok(Abbr::Literal(literal)) if literal == "a=b");
+        assert_variant!(Abbr::parse("\\=src"), Ok(Abbr::Literal(literal)) if literal == "=src");
+        assert_variant!(Abbr::parse("src\\^"), Ok(Abbr::Literal(literal)) if literal == "src^");
+
+        assert_variant!(Abbr::parse("=src^"), Err(Error::InvalidPattern(_)));
+        assert_variant!(Abbr::parse("$v*"), Err(Error::InvalidPattern(_)));
+        assert_variant!(Abbr::parse("=-"), Err(Error::InvalidPattern(_)));
+    }
+
+    #[test]
+    fn test_compare_anchored_abbr() {
+        let abbr = Abbr::parse("=src").unwrap();
+        assert_variant!(abbr.compare("SRC"), Some(Complete));
+        assert_variant!(abbr.compare("src_"), None);
+        assert_variant!(abbr.compare("some_resource"), None);
+
+        let abbr = Abbr::parse("src^").unwrap();
+        assert_variant!(abbr.compare("src"), Some(Complete));
+        assert_variant!(abbr.compare("Src-old"), Some(Prefix(4)));
+        assert_variant!(abbr.compare("some_resource"), None);
+
+        let abbr = Abbr::parse("$test").unwrap();
+        assert_variant!(abbr.compare("test"), Some(Complete));
+        assert_variant!(abbr.compare("unit_test"), Some(Boundary(0)));
+        assert_variant!(abbr.compare("latest"), Some(Subsequence(0)));
+        assert_variant!(abbr.compare("tests"), None);
+    }
+
+    #[test]
+    fn test_parse_negated_abbr() {
+        let (positive, negated) = assert_variant!(
+            Abbr::parse("api!old!=legacy"),
+            Ok(Abbr::Negated { positive, negated }) => (positive, negated)
+        );
+        assert_variant!(*positive, Abbr::Literal(literal) if literal == "api");
+        assert_variant!(negated.as_slice(), [Abbr::Literal(old), Abbr::Exact(legacy)] if old == "old" && legacy == "legacy");
+
+        let positive = assert_variant!(
+            Abbr::parse("!old"),
+            Ok(Abbr::Negated { positive, .. }) => positive
+        );
+        assert_variant!(*positive, Abbr::Wildcard);
+
+        let negated = assert_variant!(
+            Abbr::parse("{a!b}!{c!d}"),
+            Ok(Abbr::Negated { negated, .. }) => negated
+        );
+        assert_variant!(negated.as_slice(), [Abbr::Regex(_)]);
+
+        assert_variant!(Abbr::parse("a\\!b"), Ok(Abbr::Literal(literal)) if literal == "a!b");
+        assert_variant!(Abbr::parse("api!"), Err(Error::InvalidPattern(_)));
+        assert_variant!(Abbr::parse("api!!old"), Err(Error::InvalidPattern(_)));
+    }
+
+    #[test]
+    fn test_compare_negated_abbr() {
+        let abbr = Abbr::parse("api!old").unwrap();
+        assert_variant!(abbr.compare("api"), Some(Complete));
+        assert_variant!(abbr.compare("api-legacy"), Some(Prefix(7)));
+        assert_variant!(abbr.compare("api-old"), None);
+        assert_variant!(abbr.compare("API-OLD"), None);
+        assert_variant!(abbr.compare("xyz"), None);
+
+        let abbr = Abbr::parse("api!old!legacy").unwrap();
+        assert_variant!(abbr.compare("api"), Some(Complete));
+        assert_variant!(abbr.compare("api-legacy"), None);
+
+        let abbr = Abbr::parse("api!$-old").unwrap();
+        assert_variant!(abbr.compare("api-old"), None);
+        assert_variant!(abbr.compare("api-old-v2"), Some(Prefix(7)));
+
+        let abbr = Abbr::parse("!*-old").unwrap();
+        assert_variant!(abbr.compare("api"), Some(Complete));
+        assert_variant!(abbr.compare("api-old"), None);
+    }
+
+    #[test]
+    fn test_parse_tokens() {
+        let tokens = assert_variant!(
+            Abbr::parse("svc  auth!old {a b}"),
+            Ok(Abbr::Tokens(tokens)) => tokens
+        );
+        assert_variant!(
+            tokens.as_slice(),
+            [Abbr::Literal(_), Abbr::Negated { .. }, Abbr::Regex(_)]
+        );
+
+        assert_variant!(Abbr::parse(" svc "), Ok(Abbr::Literal(literal)) if literal == "svc");
+        assert_variant!(Abbr::parse("a\\ b"), Ok(Abbr::Literal(literal)) if literal == "a b");
+        assert_variant!(Abbr::parse("  "), Ok(Abbr::Literal(literal)) if literal == "  ");
+    }
+
+    #[test]
+    fn test_compare_tokens() {
+        let abbr = Abbr::parse("svc auth").unwrap();
+        assert_variant!(abbr.compare("auth-service"), Some(Subsequence(_)));
+        assert_variant!(abbr.compare("service-authz"), Some(Subsequence(_)));
+        assert_variant!(abbr.compare("auth"), None);
+
+        // The worst of the tokens' congruences is used.
+        let abbr = Abbr::parse("auth serv").unwrap();
+        assert_variant!(abbr.compare("auth-service"), Some(Boundary(0)));
+        assert_variant!(abbr.compare("service-auth"), Some(Boundary(0)));
+        assert_variant!(abbr.compare("auth"), None);
+
+        let abbr = Abbr::parse("auth -").unwrap();
+        assert_variant!(abbr.compare("authz"), Some(Prefix(1)));
+
+        let abbr = Abbr::parse("svc !old").unwrap();
+        assert_variant!(abbr.compare("service"), Some(Subsequence(_)));
+        assert_variant!(abbr.compare("old-service"), None);
+    }
+
+    #[test]
+    fn test_compare_patterns() {
+        let abbr = Abbr::parse("v[0-9]*").unwrap();
+        assert_variant!(abbr.compare("v1"), Some(Complete));
+        assert_variant!(abbr.compare("V12-beta"), Some(Complete));
+        assert_variant!(abbr.compare("vx"), None);
+        assert_variant!(abbr.compare("av1"), None);
+
+        let abbr = Abbr::parse("{^v\\d+$}").unwrap();
+        assert_variant!(abbr.compare("v12"), Some(Complete));
+        assert_variant!(abbr.compare("V12"), Some(Complete));
+        assert_variant!(abbr.compare("v12-beta"), None);
+
+        // Regexes are not anchored.
+        let abbr = Abbr::parse("{cfg}").unwrap();
+        assert_variant!(abbr.compare("my-cfg-files"), Some(Complete));
+
+        let matcher = ExactMatcher;
+        assert_variant!(abbr.compare_with(&matcher, "my-cfg-files"), Some(()));
     }
 
     #[test]
@@ -165,9 +1090,9 @@ mod test {
 
     #[test]
     fn test_order_paths() {
-        fn sort<'a>(paths: &'a Vec<&'a str>, abbr: &str) -> Vec<&'a str> {
+        fn sort<'a>(paths: &[&'a str], abbr: &str) -> Vec<&'a str> {
             let abbr = Abbr::new_sanitized(abbr);
-            let mut paths = paths.clone();
+            let mut paths = paths.to_vec();
             paths.sort_by_key(|path| abbr.compare(path).unwrap());
 
             paths
@@ -178,5 +1103,11 @@ mod test {
 
         let paths = vec!["veccentric", "vehiccles"];
         assert_eq!(paths, sort(&paths, "vecc"));
+
+        let paths = vec!["foo-bar-service", "fabulous"];
+        assert_eq!(paths, sort(&paths, "fbs"));
+
+        let paths = vec!["doc", "docs", "documentation-archive-2019"];
+        assert_eq!(paths, sort(&paths, "doc"));
     }
 }
@@ -0,0 +1,514 @@
+//! Archives searched as if they were dirs.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use flate2::read::GzDecoder;
+
+use kn::{Entry, Error, Lister, MemoryTree, Metadata};
+
+use crate::cache;
+
+/// The kind of an archive, recognized by the extension of its name.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ArchiveKind {
+    /// `.zip`.
+    Zip,
+
+    /// `.tar`.
+    Tar,
+
+    /// `.tar.gz` or `.tgz`.
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Returns the kind of the archive with the name, ignoring the case.
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks if the file with the name may be an archive.
+pub fn is_archive_name(name: &str) -> bool {
+    ArchiveKind::from_name(name).is_some()
+}
+
+/// Finds the archive containing the path (or being the path itself). Returns
+/// the archive's path and kind or `None` if the path is not in an archive.
+/// Archives in archives are not supported, only the outermost one is found.
+fn find_archive(path: &Path) -> Option<(&Path, ArchiveKind)> {
+    let ancestors = path.ancestors().collect::<Vec<_>>();
+
+    ancestors.into_iter().rev().find_map(|ancestor| {
+        // Check the name first to avoid reading every ancestor's metadata.
+        let kind = ArchiveKind::from_name(ancestor.file_name()?.to_str()?)?;
+
+        ancestor.is_file().then_some((ancestor, kind))
+    })
+}
+
+/// Checks if the path inside an archive stays inside it.
+fn is_enclosed(path: &Path) -> bool {
+    path.components().all(|component| {
+        matches!(component, Component::Normal(_) | Component::CurDir)
+    })
+}
+
+/// Reads the archive's dirs and symlinks into a tree rooted at the archive's
+/// path. Dirs without their own entries are added along with their children.
+fn read_tree(archive: &Path, kind: ArchiveKind) -> Result<MemoryTree, Error> {
+    let mut tree = MemoryTree::new().dir(archive);
+    let file = BufReader::new(File::open(archive)?);
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file)
+                .map_err(|error| Error::Archive(error.to_string()))?;
+
+            for i in 0..zip.len() {
+                // Only the names are read, nothing is decompressed.
+                let file = zip
+                    .by_index_raw(i)
+                    .map_err(|error| Error::Archive(error.to_string()))?;
+                let path = match file.enclosed_name() {
+                    Some(path) => path,
+                    None => continue,
+                };
+
+                if file.is_dir() {
+                    tree = tree.dir(archive.join(path));
+                } else if let Some(parent) = path.parent() {
+                    tree = tree.dir(archive.join(parent));
+                }
+            }
+        }
+        ArchiveKind::Tar => tree = read_tar_tree(tree, archive, file)?,
+        ArchiveKind::TarGz =>
+            tree = read_tar_tree(tree, archive, GzDecoder::new(file))?,
+    }
+
+    Ok(tree)
+}
+
+/// Reads the tar archive's dirs and symlinks into the tree.
+fn read_tar_tree<R>(
+    mut tree: MemoryTree,
+    archive: &Path,
+    reader: R,
+) -> Result<MemoryTree, Error>
+where
+    R: Read,
+{
+    for entry in tar::Archive::new(reader).entries()? {
+        let entry = entry?;
+        let path = entry.path()?;
+
+        if !is_enclosed(&path) {
+            continue;
+        }
+
+        let entry_type = entry.header().entry_type();
+
+        if entry_type.is_dir() {
+            tree = tree.dir(archive.join(&path));
+        } else if entry_type.is_symlink() {
+            if let Some(target) = entry.link_name()? {
+                tree = tree.symlink(archive.join(&path), target.into_owned());
+            }
+        } else if let Some(parent) = path.parent() {
+            tree = tree.dir(archive.join(parent));
+        }
+    }
+
+    Ok(tree)
+}
+
+/// Lists `.zip`, `.tar`, `.tar.gz` and `.tgz` archives as if they were dirs.
+///
+/// Archives are the files listed by the inner [`Lister`](Lister), listed as
+/// dirs. Their contents are read once and kept in memory. Unreadable archives
+/// are listed as empty. The dirs in archives can be
+/// [extracted](extract) to enter them.
+pub struct ArchiveLister {
+    inner: Arc<dyn Lister>,
+    trees: Mutex<HashMap<PathBuf, Arc<MemoryTree>>>,
+}
+
+impl ArchiveLister {
+    /// Creates a lister listing the dirs outside archives with the inner
+    /// lister.
+    pub fn new(inner: Arc<dyn Lister>) -> Self {
+        Self {
+            inner,
+            trees: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the archive's tree, reading it if it's not been read yet.
+    fn tree(&self, archive: &Path, kind: ArchiveKind) -> Arc<MemoryTree> {
+        // The trees are only inserted, so a panicking thread leaves them valid.
+        let lock = || self.trees.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(tree) = lock().get(archive) {
+            return Arc::clone(tree);
+        }
+
+        // Other threads list other dirs while the archive is being read. If
+        // two threads read it at once, the first tree is kept.
+        let tree = Arc::new(read_tree(archive, kind).unwrap_or_default());
+
+        Arc::clone(lock().entry(archive.into()).or_insert(tree))
+    }
+}
+
+impl Lister for ArchiveLister {
+    fn list(&self, dir: &Path) -> Vec<Entry> {
+        if let Some((archive, kind)) = find_archive(dir) {
+            return self.tree(archive, kind).list(dir);
+        }
+
+        let mut entries = self.inner.list(dir);
+
+        for entry in &mut entries {
+            if entry.is_file && is_archive_name(&entry.name) {
+                entry.is_file = false;
+            }
+        }
+
+        entries
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        match find_archive(path) {
+            Some((archive, kind)) => self.tree(archive, kind).is_dir(path),
+            None => self.inner.is_dir(path),
+        }
+    }
+
+    /// Returns the archive's metadata for the dirs in it.
+    fn metadata(&self, path: &Path) -> Metadata {
+        match find_archive(path) {
+            Some((archive, _)) => self.inner.metadata(archive),
+            None => self.inner.metadata(path),
+        }
+    }
+
+    /// Returns the archive's device for the dirs in it.
+    fn device(&self, path: &Path) -> Option<u64> {
+        match find_archive(path) {
+            Some((archive, _)) => self.inner.device(archive),
+            None => self.inner.device(path),
+        }
+    }
+}
+
+/// Returns the default location of extracted archives, `kn/archives` in the
+/// [cache dir](dirs::cache_dir) (usually `~/.cache/kn/archives`).
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|cache_dir| cache_dir.join("kn").join("archives"))
+}
+
+/// The `extract` subcommand.
+///
+/// Extracts the archive containing the path into a subdir of `dir` and
+/// returns the path's counterpart there. The subdir is named after the
+/// archive's path and mtime, so an archive is extracted again only when it
+/// changes. Then the previous extractions of the archive are removed. Returns
+/// the path itself if it's not in an archive.
+pub fn extract(path: &Path, dir: &Path) -> Result<PathBuf, Error> {
+    let (archive, kind) = match find_archive(path) {
+        Some(found) => found,
+        None => return Ok(path.into()),
+    };
+    // The archive is one of the path's ancestors.
+    let inner_path = path.strip_prefix(archive).unwrap_or(path);
+    let archive = fs::canonicalize(archive)?;
+
+    let mtime = fs::metadata(&archive)?
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let prefix = format!(
+        "{}-{:016x}-",
+        archive.file_name().unwrap_or_default().to_string_lossy(),
+        cache::hash_path(&archive)
+    );
+    let name = format!("{}{:x}", prefix, mtime.as_nanos());
+    let extracted = dir.join(&name);
+
+    if !extracted.exists() {
+        // A partially extracted archive is never entered.
+        let result = cache::create_atomically(&extracted, |tmp_dir| {
+            fs::create_dir(tmp_dir)?;
+
+            let file = BufReader::new(File::open(&archive)?);
+
+            match kind {
+                ArchiveKind::Zip => zip::ZipArchive::new(file)
+                    .and_then(|mut zip| zip.extract(tmp_dir))
+                    .map_err(|error| Error::Archive(error.to_string())),
+                ArchiveKind::Tar =>
+                    tar::Archive::new(file).unpack(tmp_dir).map_err(Error::from),
+                ArchiveKind::TarGz => tar::Archive::new(GzDecoder::new(file))
+                    .unpack(tmp_dir)
+                    .map_err(Error::from),
+            }
+        });
+
+        match result {
+            // Another process may have extracted the archive in the meantime.
+            Err(_) if extracted.is_dir() => {}
+            result => result?,
+        }
+
+        remove_stale_extractions(dir, &prefix, &name);
+    }
+
+    Ok(extracted.join(inner_path))
+}
+
+/// Removes the extractions of the archive named with the prefix, except the
+/// current one. Temporary dirs of extractions in progress are left alone.
+fn remove_stale_extractions(dir: &Path, prefix: &str, current: &str) {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let is_stale = entry.file_name().to_str().is_some_and(|name| {
+            name != current
+                && name.strip_prefix(prefix).is_some_and(|mtime| {
+                    !mtime.is_empty()
+                        && mtime.chars().all(|c| c.is_ascii_hexdigit())
+                })
+        });
+
+        if is_stale {
+            // Extractions are only a cache.
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, time::Duration};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+    use kn::{DirReader, Query, QueryOptions};
+
+    fn sorted_names(entries: Vec<Entry>) -> Vec<String> {
+        let mut names = entries
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        names.sort();
+
+        names
+    }
+
+    fn write_zip(path: &Path) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.start_file("bin/linux/kn", options).unwrap();
+        zip.write_all(b"kn").unwrap();
+        zip.add_directory("docs/", options).unwrap();
+        zip.finish().unwrap();
+    }
+
+    fn write_tar<W>(writer: W)
+    where
+        W: Write,
+    {
+        let mut tar = tar::Builder::new(writer);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        tar.append_data(&mut header.clone(), "src/sub", std::io::empty())
+            .unwrap();
+
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(2);
+        tar.append_data(&mut header.clone(), "src/main.rs", &b"kn"[..])
+            .unwrap();
+
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        tar.append_link(&mut header, "latest", "src").unwrap();
+        tar.into_inner().unwrap().flush().unwrap();
+    }
+
+    fn archives() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("real")).unwrap();
+        fs::write(root.path().join("notes.txt"), "").unwrap();
+        fs::write(root.path().join("broken.zip"), "garbage").unwrap();
+        write_zip(&root.path().join("rel-1.2.zip"));
+        write_tar(File::create(root.path().join("src.tar")).unwrap());
+        write_tar(GzEncoder::new(
+            File::create(root.path().join("src.tgz")).unwrap(),
+            Compression::default(),
+        ));
+
+        root
+    }
+
+    #[test]
+    fn test_archive_kind() {
+        assert_eq!(ArchiveKind::from_name("a.zip"), Some(ArchiveKind::Zip));
+        assert_eq!(ArchiveKind::from_name("a.TAR"), Some(ArchiveKind::Tar));
+        assert_eq!(
+            ArchiveKind::from_name("a.tar.gz"),
+            Some(ArchiveKind::TarGz)
+        );
+        assert_eq!(ArchiveKind::from_name("a.tgz"), Some(ArchiveKind::TarGz));
+        assert_eq!(ArchiveKind::from_name("a.gz"), None);
+        assert_eq!(ArchiveKind::from_name("zip"), None);
+    }
+
+    #[test]
+    fn test_archive_lister() {
+        let root = archives();
+        let root = root.path();
+        let lister = ArchiveLister::new(Arc::new(DirReader));
+
+        let entries = lister.list(root);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.is_file == (entry.name == "notes.txt")));
+        assert_eq!(
+            sorted_names(entries),
+            [
+                "broken.zip",
+                "notes.txt",
+                "real",
+                "rel-1.2.zip",
+                "src.tar",
+                "src.tgz"
+            ]
+        );
+        assert_eq!(
+            sorted_names(lister.list(&root.join("rel-1.2.zip"))),
+            ["bin", "docs"]
+        );
+        assert!(lister.is_dir(&root.join("rel-1.2.zip")));
+        assert!(lister.is_dir(&root.join("rel-1.2.zip/bin/linux")));
+        assert!(!lister.is_dir(&root.join("rel-1.2.zip/bin/linux/kn")));
+
+        for tar in ["src.tar", "src.tgz"] {
+            assert_eq!(
+                lister.list(&root.join(tar)),
+                [
+                    Entry {
+                        name: "src".into(),
+                        is_symlink: false,
+                        is_file: false,
+                    },
+                    Entry {
+                        name: "latest".into(),
+                        is_symlink: true,
+                        is_file: false,
+                    },
+                ]
+            );
+            assert_eq!(
+                sorted_names(lister.list(&root.join(tar).join("latest"))),
+                ["sub"]
+            );
+        }
+
+        assert!(lister.list(&root.join("broken.zip")).is_empty());
+        assert!(!lister.is_dir(&root.join("broken.zip")));
+        assert!(lister.is_dir(&root.join("real")));
+
+        let options =
+            QueryOptions::new().start_dir(root).lister(Arc::new(lister));
+        let query = Query::with_options("re/bi/li", options.clone());
+        assert_eq!(
+            query.find().unwrap().path,
+            root.join("rel-1.2.zip/bin/linux")
+        );
+        let query = Query::with_options("src.tar/la/su", options);
+        assert_eq!(query.find().unwrap().path, root.join("src.tar/latest/sub"));
+
+        let lister = ArchiveLister::new(Arc::new(DirReader));
+        assert_eq!(
+            lister.device(&root.join("rel-1.2.zip/bin/linux")),
+            DirReader.device(&root.join("rel-1.2.zip"))
+        );
+        assert!(lister.device(root).is_some() || cfg!(not(unix)));
+
+        // Only the archives listed by the inner lister are listed.
+        let tree = MemoryTree::new().dir(root.join("real"));
+        let lister = ArchiveLister::new(Arc::new(tree));
+        assert_eq!(sorted_names(lister.list(root)), ["real"]);
+    }
+
+    #[test]
+    fn test_extract() {
+        let root = archives();
+        let root = root.path();
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let path = root.join("rel-1.2.zip/bin/linux");
+        let extracted = extract(&path, cache_dir.path()).unwrap();
+        assert!(extracted.starts_with(cache_dir.path()));
+        assert!(extracted.ends_with("bin/linux"));
+        assert_eq!(fs::read(extracted.join("kn")).unwrap(), b"kn");
+        assert_eq!(extract(&path, cache_dir.path()).unwrap(), extracted);
+
+        let extracted =
+            extract(&root.join("src.tgz/latest/sub"), cache_dir.path())
+                .unwrap();
+        assert!(extracted.is_dir());
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 2);
+
+        assert_eq!(
+            extract(&root.join("real"), cache_dir.path()).unwrap(),
+            root.join("real")
+        );
+        assert_variant!(
+            extract(&root.join("broken.zip"), cache_dir.path()),
+            Err(Error::Archive(_))
+        );
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 2);
+
+        // The archive has changed.
+        let extracted = extract(&path, cache_dir.path()).unwrap();
+        let zip = root.join("rel-1.2.zip");
+        write_zip(&zip);
+        File::options()
+            .write(true)
+            .open(&zip)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let extracted_again = extract(&path, cache_dir.path()).unwrap();
+        assert_ne!(extracted_again, extracted);
+        assert!(extracted_again.is_dir());
+        assert!(!extracted.exists());
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 2);
+    }
+}
@@ -1,11 +1,21 @@
 //! Arg parsing.
 
-use std::path::PathBuf;
+use std::{
+    env,
+    ffi::OsString,
+    num::NonZeroUsize,
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
 
-use crate::error::Error;
+use kn::{DefaultMatcher, Error, Exclusion, Limits, QueryOptions, Tiebreak};
+
+use crate::config::{Config, MatcherKind};
 
 /// Subcommand with its args.
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Subcommand {
     /// The [`init`](crate::init::init) subcommand.
     Init {
@@ -15,16 +25,57 @@ pub enum Subcommand {
         /// The value of the `--exclude-old-pwd` flag.
         exclude_old_pwd: bool,
     },
-    /// The [`query`](crate::query::query) subcommand.
-    Query {
-        /// The abbr.
-        abbr: String,
+    /// The [`query`](kn::Query) subcommand.
+    Query(QueryArgs),
+
+    /// The [`daemon`](crate::daemon::daemon) subcommand.
+    Daemon {
+        /// The values of the `--root` arg, the dirs to index.
+        roots: Vec<PathBuf>,
+    },
+
+    /// The [`index build`](crate::index::build) subcommand.
+    IndexBuild {
+        /// The dirs to index.
+        roots: Vec<PathBuf>,
+    },
+
+    /// The [`index update`](crate::index::update) subcommand.
+    IndexUpdate,
 
-        /// Path excluded from search.
-        excluded: Option<PathBuf>,
+    /// The [`extract`](crate::archive::extract) subcommand.
+    Extract {
+        /// The path in an archive.
+        path: PathBuf,
     },
 }
 
+/// The args of the [`query`](kn::Query) subcommand, merged with the
+/// config file.
+#[derive(Debug)]
+pub struct QueryArgs {
+    /// The abbreviation. Depending on the config, the values of `--abbr` are
+    /// either kept as consecutive components or joined with spaces into tokens
+    /// of a single component.
+    pub abbrs: Vec<String>,
+
+    /// The value of the `--matcher` arg or the matcher from the config file.
+    pub matcher: MatcherKind,
+
+    /// The options of the query. The default matcher's typo tolerance is set
+    /// in them.
+    pub options: QueryOptions,
+
+    /// Whether the listings of the dirs are cached on the disk.
+    pub listing_cache: bool,
+
+    /// Whether the dirs are listed from the prebuilt index.
+    pub index: bool,
+
+    /// Whether archives are searched as if they were dirs.
+    pub archives: bool,
+}
+
 /// The value of the `--shell` arg.
 #[derive(Debug)]
 pub enum Shell {
@@ -43,15 +94,46 @@ const SHELL_ARG: &str = "--shell";
 const ABBR_ARG: &str = "--abbr";
 const EXCLUDE_OLD_PWD_ARG: &str = "--exclude-old-pwd";
 const EXCLUDE_ARG: &str = "--exclude";
+const AVOID_ARG: &str = "--avoid";
+const MATCHER_ARG: &str = "--matcher";
+const TYPOS_ARG: &str = "--typos";
+const TIEBREAK_ARG: &str = "--tiebreak";
+const BEAM_WIDTH_ARG: &str = "--beam-width";
+const MAX_DIRS_ARG: &str = "--max-dirs";
+const THREADS_ARG: &str = "--threads";
+const TIMEOUT_ARG: &str = "--timeout";
+const ONE_FILE_SYSTEM_ARG: &str = "--one-file-system";
+const LISTING_CACHE_ARG: &str = "--listing-cache";
+const INDEX_ARG: &str = "--index";
+const ARCHIVES_ARG: &str = "--archives";
 const FISH_ARG: &str = "fish";
 const BASH_ARG: &str = "bash";
 const ZSH_ARG: &str = "zsh";
 const INIT_SUBCOMMAND: &str = "init";
 const QUERY_SUBCOMMAND: &str = "query";
+const DAEMON_SUBCOMMAND: &str = "daemon";
+const ROOT_ARG: &str = "--root";
+const INDEX_SUBCOMMAND: &str = "index";
+const INDEX_ACTION_ARG: &str = "index action";
+const BUILD_ACTION: &str = "build";
+const UPDATE_ACTION: &str = "update";
+const EXTRACT_SUBCOMMAND: &str = "extract";
 
 /// Parses CLI args.
 pub fn parse_args() -> Result<Subcommand, Error> {
-    let mut pargs = pico_args::Arguments::from_env();
+    parse_args_from(env::args_os().skip(1).collect(), Config::load)
+}
+
+/// Parses args without the name of the binary. The config file is loaded only
+/// if it's needed.
+pub fn parse_args_from<F>(
+    args: Vec<OsString>,
+    load_config: F,
+) -> Result<Subcommand, Error>
+where
+    F: FnOnce() -> Result<Config, Error>,
+{
+    let mut pargs = pico_args::Arguments::from_vec(args);
 
     let subcommand = pargs
         .subcommand()?
@@ -76,14 +158,210 @@ pub fn parse_args() -> Result<Subcommand, Error> {
             })
         }
         QUERY_SUBCOMMAND => {
-            let abbr = pargs.value_from_str(ABBR_ARG)?;
-            let excluded = pargs.opt_value_from_os_str::<_, _, Error>(
-                EXCLUDE_ARG,
-                |os_str| Ok(PathBuf::from(os_str)),
-            )?;
+            let abbrs: Vec<String> = pargs.values_from_str(ABBR_ARG)?;
+            if abbrs.is_empty() {
+                return Err(
+                    pico_args::Error::MissingOption(ABBR_ARG.into()).into()
+                );
+            }
+            let excluded =
+                pargs.values_from_os_str(EXCLUDE_ARG, Exclusion::parse)?;
+            let avoided = pargs
+                .values_from_os_str::<_, _, Error>(AVOID_ARG, |os_str| {
+                    Ok(PathBuf::from(os_str))
+                })?;
+            let matcher = pargs
+                .opt_value_from_str::<_, String>(MATCHER_ARG)?
+                .map(|matcher| matcher.parse())
+                .transpose()?;
+            let typos = pargs.contains(TYPOS_ARG);
+            let tiebreaks = pargs
+                .opt_value_from_str::<_, String>(TIEBREAK_ARG)?
+                .map(|tiebreaks| Tiebreak::parse_list(&tiebreaks))
+                .transpose()?;
+            let beam_width = pargs.opt_value_from_str(BEAM_WIDTH_ARG)?;
+            let max_dirs = pargs.opt_value_from_str(MAX_DIRS_ARG)?;
+            let threads = pargs.opt_value_from_str(THREADS_ARG)?;
+            let timeout = pargs.opt_value_from_str(TIMEOUT_ARG)?;
+            let one_file_system = pargs.contains(ONE_FILE_SYSTEM_ARG);
+            let listing_cache = pargs.contains(LISTING_CACHE_ARG);
+            let index = pargs.contains(INDEX_ARG);
+            let archives = pargs.contains(ARCHIVES_ARG);
+
+            // The args override the config file.
+            let config = load_config()?;
+            let abbrs = if config.join_args {
+                vec![abbrs.join(" ")]
+            } else {
+                abbrs
+            };
+            let threads = threads.or(config.threads).unwrap_or_else(|| {
+                thread::available_parallelism().map_or(1, NonZeroUsize::get)
+            });
+            let limits = Limits {
+                beam_width: beam_width.or(config.beam_width),
+                max_dirs: max_dirs.or(config.max_dirs),
+                one_file_system: one_file_system || config.one_file_system,
+            };
+            let mut options = QueryOptions::new()
+                .matcher(DefaultMatcher {
+                    typos: typos || config.typos,
+                })
+                .tiebreaks(tiebreaks.unwrap_or(config.tiebreak))
+                .limits(limits)
+                .threads(threads);
+
+            for exclusion in excluded {
+                options = options.exclude(exclusion);
+            }
+
+            for path in avoided {
+                options = options.avoid(path);
+            }
+
+            if let Some(timeout) = timeout.or(config.timeout) {
+                options = options.timeout(Duration::from_millis(timeout));
+            }
+
+            Ok(Subcommand::Query(QueryArgs {
+                abbrs,
+                matcher: matcher.unwrap_or(config.matcher),
+                options,
+                listing_cache: listing_cache || config.listing_cache,
+                index: index || config.index,
+                archives: archives || config.archives,
+            }))
+        }
+        DAEMON_SUBCOMMAND => {
+            let roots = pargs
+                .values_from_os_str::<_, _, Error>(ROOT_ARG, |os_str| {
+                    Ok(PathBuf::from(os_str))
+                })?;
 
-            Ok(Subcommand::Query { abbr, excluded })
+            Ok(Subcommand::Daemon { roots })
+        }
+        INDEX_SUBCOMMAND => {
+            let action = pargs
+                .subcommand()?
+                .ok_or(pico_args::Error::MissingArgument)?;
+
+            match action.as_str() {
+                BUILD_ACTION => {
+                    let roots =
+                        pargs.finish().into_iter().map(PathBuf::from).collect();
+
+                    Ok(Subcommand::IndexBuild { roots })
+                }
+                UPDATE_ACTION => Ok(Subcommand::IndexUpdate),
+                _ => Err(Error::InvalidArgValue(INDEX_ACTION_ARG.to_string())),
+            }
+        }
+        EXTRACT_SUBCOMMAND => {
+            let path = pargs.free_from_os_str::<_, Error>(|os_str| {
+                Ok(PathBuf::from(os_str))
+            })?;
+
+            Ok(Subcommand::Extract { path })
         }
         _ => Err(Error::InvalidArgValue(SUBCOMMAND_ARG.to_string())),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str], config: Config) -> Result<Subcommand, Error> {
+        let args = args.iter().map(OsString::from).collect();
+
+        parse_args_from(args, || Ok(config))
+    }
+
+    fn parse_query(args: &[&str], config: Config) -> QueryArgs {
+        assert_variant!(parse(args, config), Ok(Subcommand::Query(args)) => args)
+    }
+
+    #[test]
+    fn test_parse_abbrs() {
+        let args = parse_query(
+            &[
+                "query", "--abbr", "pr", "--abbr", "{^a/b}", "--abbr", "../kn",
+            ],
+            Config::default(),
+        );
+        assert_eq!(args.abbrs, ["pr", "{^a/b}", "../kn"]);
+
+        let args =
+            parse_query(&["query", "--abbr", "pr/kn"], Config::default());
+        assert_eq!(args.abbrs, ["pr/kn"]);
+
+        assert_variant!(
+            parse(&["query"], Config::default()),
+            Err(Error::Args(pico_args::Error::MissingOption(_)))
+        );
+    }
+
+    #[test]
+    fn test_parse_exclusions() {
+        use kn::{MemoryTree, Query};
+
+        let tree = MemoryTree::new().dir("/r/foo/foo").dir("/r/bar/foo");
+        let find = |args: &[&str]| {
+            let args = parse_query(args, Config::default());
+            let options = args
+                .options
+                .current_dir("/r")
+                .lister(std::sync::Arc::new(tree.clone()));
+
+            Query::with_args(args.abbrs, options)
+                .find()
+                .map(|found| found.path)
+        };
+
+        // A name excludes the dirs with it on every level.
+        assert_variant!(
+            find(&["query", "--abbr", "-/foo", "--exclude", "foo"]),
+            Err(Error::PathNotFound)
+        );
+        // A path excludes only the dir.
+        assert_eq!(
+            find(&["query", "--abbr", "-/foo", "--exclude", "./foo"]).unwrap(),
+            PathBuf::from("/r/bar/foo")
+        );
+        // An avoided dir is returned only if it's the only match.
+        assert_eq!(
+            find(&["query", "--abbr", "-/foo"]).unwrap(),
+            PathBuf::from("/r/foo/foo")
+        );
+        assert_eq!(
+            find(&["query", "--abbr", "-/foo", "--avoid", "./foo/foo"])
+                .unwrap(),
+            PathBuf::from("/r/bar/foo")
+        );
+        assert_eq!(
+            find(&[
+                "query",
+                "--abbr",
+                "-/foo",
+                "--avoid",
+                "./foo/foo",
+                "--exclude",
+                "./bar",
+            ])
+            .unwrap(),
+            PathBuf::from("/r/foo/foo")
+        );
+    }
+
+    #[test]
+    fn test_parse_joined_abbrs() {
+        let config = Config {
+            join_args: true,
+            ..Config::default()
+        };
+        let args =
+            parse_query(&["query", "--abbr", "svc", "--abbr", "auth"], config);
+
+        assert_eq!(args.abbrs, ["svc auth"]);
+    }
+}
@@ -0,0 +1,457 @@
+//! The listing cache, the format of listings shared with the index and writing
+//! cached files.
+
+use std::{
+    ffi::OsString,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use kn::{DirReader, Entry, Lister};
+
+use crate::archive;
+
+/// The time after modification in which a dir's listing is not cached.
+///
+/// The mtime is updated with limited precision, so a dir modified right after
+/// being listed may keep the same mtime. Its listing would never be
+/// invalidated.
+pub const RACY_MARGIN: Duration = Duration::from_secs(2);
+
+/// The age after which a cached listing is removed, so that the listings of
+/// removed or forgotten dirs don't accumulate.
+const MAX_LISTING_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The time between prunings of the listing cache.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The file in the listing cache touched whenever the cache is pruned.
+const PRUNED_MARKER: &str = "pruned";
+
+/// Caches dirs' listings on the disk, one file per dir.
+///
+/// A listing is keyed by the dir's path and mtime. Creating, removing or
+/// renaming a child updates the mtime, so the listing is read again and never
+/// contains a removed dir. Listings of recently modified dirs are not cached
+/// (see [`RACY_MARGIN`](RACY_MARGIN)). Once a day, listings written more than
+/// 30 days ago are removed.
+#[derive(Clone, Debug)]
+pub struct ListingCache {
+    dir: PathBuf,
+    racy_margin: Duration,
+}
+
+impl ListingCache {
+    /// Creates the cache stored in the dir.
+    pub fn new(dir: PathBuf, racy_margin: Duration) -> Self {
+        Self { dir, racy_margin }
+    }
+
+    /// Returns the default location of the cache, `kn/listings` in the
+    /// [cache dir](dirs::cache_dir) (usually `~/.cache/kn/listings`).
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|cache_dir| cache_dir.join("kn").join("listings"))
+    }
+
+    /// Returns the location of the dir's listing.
+    fn listing_path(&self, dir: &Path) -> PathBuf {
+        self.dir.join(format!("{:016x}", hash_path(dir)))
+    }
+
+    /// Reads the dir's listing if it's been cached with the same mtime.
+    fn read(&self, dir: &Path, mtime: &str) -> Option<Vec<Entry>> {
+        let contents = fs::read_to_string(self.listing_path(dir)).ok()?;
+        let (cached_dir, cached_mtime, entries) = parse_listing(&contents)?;
+
+        (cached_dir == dir.to_str()? && cached_mtime == mtime)
+            .then_some(entries)
+    }
+
+    /// Caches the dir's listing.
+    fn write(
+        &self,
+        dir: &Path,
+        mtime: &str,
+        entries: &[Entry],
+    ) -> io::Result<()> {
+        let contents = match format_listing(dir, mtime, entries) {
+            Some(contents) => contents,
+            None => return Ok(()),
+        };
+
+        create_atomically(&self.listing_path(dir), |tmp_path| {
+            fs::write(tmp_path, contents)
+        })
+    }
+
+    /// Removes the listings written at least `max_age` ago, along with
+    /// leftover temporary files. Returns the number of removed files.
+    pub fn prune(&self, max_age: Duration) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut n_removed = 0;
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+
+            if entry.file_name() == PRUNED_MARKER {
+                continue;
+            }
+
+            let is_old = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|mtime| {
+                    now.duration_since(mtime).is_ok_and(|age| age >= max_age)
+                });
+
+            if is_old && fs::remove_file(entry.path()).is_ok() {
+                n_removed += 1;
+            }
+        }
+
+        Ok(n_removed)
+    }
+
+    /// Prunes the cache if it's not been pruned for a day.
+    fn prune_if_due(&self) -> io::Result<()> {
+        let marker = self.dir.join(PRUNED_MARKER);
+        let is_due = fs::metadata(&marker)
+            .and_then(|metadata| metadata.modified())
+            .map_or(true, |pruned| {
+                SystemTime::now()
+                    .duration_since(pruned)
+                    .is_ok_and(|age| age >= PRUNE_INTERVAL)
+            });
+
+        if is_due {
+            fs::write(&marker, "")?;
+            self.prune(MAX_LISTING_AGE)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Lister for ListingCache {
+    fn list(&self, dir: &Path) -> Vec<Entry> {
+        let mtime = match settled_mtime(dir, self.racy_margin) {
+            Some(mtime) => mtime,
+            None => return list_dir(dir),
+        };
+
+        if let Some(entries) = self.read(dir, &mtime) {
+            return entries;
+        }
+
+        let entries = list_dir(dir);
+        // The cache is only an optimization.
+        let _ = self
+            .write(dir, &mtime, &entries)
+            .and_then(|()| self.prune_if_due());
+
+        entries
+    }
+}
+
+/// Lists the dir with [`DirReader`](DirReader), keeping only the files which
+/// may be [archives](archive::is_archive_name), so that stored listings stay
+/// small.
+pub fn list_dir(dir: &Path) -> Vec<Entry> {
+    let mut entries = DirReader.list(dir);
+    entries.retain(|entry| {
+        !entry.is_file || archive::is_archive_name(&entry.name)
+    });
+
+    entries
+}
+
+/// Hashes the path with 64-bit FNV-1a. Unlike
+/// [`DefaultHasher`](std::collections::hash_map::DefaultHasher), the hash
+/// doesn't change between Rust versions, so the files named after it are
+/// found by every build of `_kn`.
+pub fn hash_path(path: &Path) -> u64 {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+
+        path.as_os_str().as_bytes()
+    };
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy();
+    #[cfg(not(unix))]
+    let bytes = bytes.as_bytes();
+
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Distinguishes the temporary files of different threads.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates the file or dir at the path with `create`, called with a temporary
+/// path next to it, and renames it into place so that other processes never
+/// see it partially written. The parent dirs are created and the temporary
+/// file or dir is removed on failure.
+pub fn create_atomically<F, E>(path: &Path, create: F) -> Result<(), E>
+where
+    F: FnOnce(&Path) -> Result<(), E>,
+    E: From<io::Error>,
+{
+    let mut tmp_path = OsString::from(path);
+    tmp_path.push(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = PathBuf::from(tmp_path);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let result = create(&tmp_path)
+        .and_then(|()| fs::rename(&tmp_path, path).map_err(E::from));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path)
+            .or_else(|_| fs::remove_dir_all(&tmp_path));
+    }
+
+    result
+}
+
+const DIR_PREFIX: &str = "d ";
+const SYMLINK_PREFIX: &str = "l ";
+const FILE_PREFIX: &str = "f ";
+
+/// Returns the dir's formatted mtime, or `None` if it can't be read or the dir
+/// has been modified within the racy margin (see [`RACY_MARGIN`](RACY_MARGIN)).
+pub fn settled_mtime(dir: &Path, racy_margin: Duration) -> Option<String> {
+    let mtime = fs::metadata(dir)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    let is_settled = SystemTime::now()
+        .duration_since(mtime)
+        .is_ok_and(|age| age >= racy_margin);
+
+    if is_settled {
+        format_mtime(mtime)
+    } else {
+        None
+    }
+}
+
+/// Formats the mtime as seconds and nanoseconds since the Unix epoch.
+fn format_mtime(mtime: SystemTime) -> Option<String> {
+    let since_epoch = mtime.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+
+    Some(format!(
+        "{} {}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    ))
+}
+
+/// Formats the listing. The first line holds the dir's path, the second its
+/// mtime and every next line a child prefixed with its type. Returns `None` if
+/// the path or any name can't be stored on a single line.
+pub fn format_listing(
+    dir: &Path,
+    mtime: &str,
+    entries: &[Entry],
+) -> Option<String> {
+    let dir = dir.to_str().filter(|dir| !dir.contains('\n'))?;
+    let mut contents = format!("{}\n{}\n", dir, mtime);
+
+    for entry in entries {
+        if entry.name.contains('\n') {
+            return None;
+        }
+
+        let prefix = if entry.is_symlink {
+            SYMLINK_PREFIX
+        } else if entry.is_file {
+            FILE_PREFIX
+        } else {
+            DIR_PREFIX
+        };
+        contents.push_str(prefix);
+        contents.push_str(&entry.name);
+        contents.push('\n');
+    }
+
+    Some(contents)
+}
+
+/// Parses the listing formatted with [`format_listing`](format_listing) into
+/// the dir's path, its mtime and its children. Returns `None` if it's
+/// malformed.
+pub fn parse_listing(contents: &str) -> Option<(&str, &str, Vec<Entry>)> {
+    let mut lines = contents.lines();
+    let dir = lines.next()?;
+    let mtime = lines.next()?;
+
+    let entries = lines
+        .map(|line| {
+            let (is_symlink, is_file) = match line.get(..DIR_PREFIX.len())? {
+                DIR_PREFIX => (false, false),
+                SYMLINK_PREFIX => (true, false),
+                FILE_PREFIX => (false, true),
+                _ => return None,
+            };
+
+            Some(Entry {
+                name: line[DIR_PREFIX.len()..].to_string(),
+                is_symlink,
+                is_file,
+            })
+        })
+        .collect::<Option<_>>()?;
+
+    Some((dir, mtime, entries))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use super::*;
+
+    fn sorted_names(mut entries: Vec<Entry>) -> Vec<String> {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn test_format_listing() {
+        let entries = vec![
+            Entry {
+                name: "foo".into(),
+                is_symlink: false,
+                is_file: false,
+            },
+            Entry {
+                name: "bar baz".into(),
+                is_symlink: true,
+                is_file: false,
+            },
+            Entry {
+                name: "qux.zip".into(),
+                is_symlink: false,
+                is_file: true,
+            },
+        ];
+        let contents =
+            format_listing(Path::new("/some/dir"), "1 2", &entries).unwrap();
+
+        assert_eq!(contents, "/some/dir\n1 2\nd foo\nl bar baz\nf qux.zip\n");
+        assert_eq!(
+            parse_listing(&contents),
+            Some(("/some/dir", "1 2", entries))
+        );
+        assert_eq!(parse_listing("/some/dir\n1 2\nx foo"), None);
+        assert_eq!(parse_listing("/some/dir"), None);
+
+        let entries = vec![Entry {
+            name: "foo\nbar".into(),
+            is_symlink: false,
+            is_file: false,
+        }];
+        assert_eq!(
+            format_listing(Path::new("/some/dir"), "1 2", &entries),
+            None
+        );
+    }
+
+    #[test]
+    fn test_hash_path() {
+        assert_eq!(hash_path(Path::new("")), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash_path(Path::new("a")), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_listing_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = ListingCache::new(cache_dir.path().into(), Duration::ZERO);
+        let dir = root.path().join("dir");
+        fs::create_dir_all(dir.join("foo")).unwrap();
+        fs::create_dir_all(dir.join("bar")).unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(dir.join("rel.zip"), "").unwrap();
+
+        // Only the files which may be archives are listed.
+        assert_eq!(sorted_names(cache.list(&dir)), ["bar", "foo", "rel.zip"]);
+        assert!(cache.listing_path(&dir).exists());
+        assert_eq!(sorted_names(cache.list(&dir)), ["bar", "foo", "rel.zip"]);
+
+        let mtime = fs::metadata(&dir).unwrap().modified().unwrap();
+        fs::remove_dir(dir.join("foo")).unwrap();
+        // The mtime changes even if the clock's resolution is coarse.
+        File::open(&dir)
+            .unwrap()
+            .set_modified(mtime - Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(sorted_names(cache.list(&dir)), ["bar", "rel.zip"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(cache.list(&dir).is_empty());
+    }
+
+    #[test]
+    fn test_listing_cache_prune() {
+        let root = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = ListingCache::new(cache_dir.path().into(), Duration::ZERO);
+        fs::create_dir_all(root.path().join("foo")).unwrap();
+
+        cache.list(root.path());
+        cache.list(&root.path().join("foo"));
+        assert!(cache.listing_path(root.path()).exists());
+        assert!(cache_dir.path().join(PRUNED_MARKER).exists());
+
+        // The listings have just been written.
+        assert_eq!(cache.prune(MAX_LISTING_AGE).unwrap(), 0);
+        assert_eq!(cache.prune(Duration::ZERO).unwrap(), 2);
+        assert!(!cache.listing_path(root.path()).exists());
+        assert!(cache_dir.path().join(PRUNED_MARKER).exists());
+    }
+
+    #[test]
+    fn test_create_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub/file");
+
+        create_atomically(&path, |tmp_path| fs::write(tmp_path, "foo"))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "foo");
+
+        // A failure leaves neither the file nor the temporary file.
+        let failed = dir.path().join("sub/failed");
+        let result = create_atomically(&failed, |tmp_path| {
+            fs::create_dir(tmp_path)?;
+
+            Err(io::Error::other("failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_dir(dir.path().join("sub")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_listing_cache_racy() {
+        let root = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = ListingCache::new(cache_dir.path().into(), RACY_MARGIN);
+        fs::create_dir(root.path().join("foo")).unwrap();
+
+        // The dir has just been modified.
+        assert_eq!(sorted_names(cache.list(root.path())), ["foo"]);
+        assert!(!cache.listing_path(root.path()).exists());
+    }
+}
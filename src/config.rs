@@ -0,0 +1,226 @@
+//! Config file.
+
+use std::{env, fs, io, path::PathBuf, str::FromStr};
+
+use serde_derive::Deserialize;
+
+use kn::{Error, Tiebreak};
+
+/// The name of a [`Matcher`](kn::Matcher), as used in the config file and in
+/// the `--matcher` arg.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatcherKind {
+    /// [`DefaultMatcher`](kn::DefaultMatcher).
+    #[default]
+    Default,
+
+    /// [`LevenshteinMatcher`](kn::LevenshteinMatcher).
+    Levenshtein,
+
+    /// [`SmithWatermanMatcher`](kn::SmithWatermanMatcher).
+    SmithWaterman,
+
+    /// [`ExactMatcher`](kn::ExactMatcher).
+    Exact,
+}
+
+const DEFAULT_MATCHER: &str = "default";
+const LEVENSHTEIN_MATCHER: &str = "levenshtein";
+const SMITH_WATERMAN_MATCHER: &str = "smith-waterman";
+const EXACT_MATCHER: &str = "exact";
+
+impl FromStr for MatcherKind {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            DEFAULT_MATCHER => Ok(Self::Default),
+            LEVENSHTEIN_MATCHER => Ok(Self::Levenshtein),
+            SMITH_WATERMAN_MATCHER => Ok(Self::SmithWaterman),
+            EXACT_MATCHER => Ok(Self::Exact),
+            _ => Err(Error::InvalidMatcher(name.to_string())),
+        }
+    }
+}
+
+/// The env var overriding the location of the config file.
+pub const CONFIG_ENV_VAR: &str = "KN_CONFIG";
+
+/// User's config.
+///
+/// It is read from `$KN_CONFIG` or, if that variable is not set, from
+/// `kn/config.toml` in the [config dir](dirs::config_dir) (usually
+/// `~/.config/kn/config.toml`). Every key is optional and the file itself may
+/// not exist.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The matcher used to compare abbreviations with dir names.
+    pub matcher: MatcherKind,
+
+    /// Whether the default matcher tolerates typos when there are no other
+    /// matches.
+    pub typos: bool,
+
+    /// Whether many args passed to `kn` are joined with spaces into tokens of
+    /// a single component instead of being treated as consecutive components.
+    pub join_args: bool,
+
+    /// Tiebreakers ordering found paths with equal congruences.
+    pub tiebreak: Vec<Tiebreak>,
+
+    /// The maximal number of findings retained on each level of the search.
+    pub beam_width: Option<usize>,
+
+    /// The maximal number of dirs read during the search.
+    pub max_dirs: Option<usize>,
+
+    /// The number of threads reading dirs. Defaults to the available
+    /// parallelism.
+    pub threads: Option<usize>,
+
+    /// The time in milliseconds after which the best path found so far is
+    /// returned.
+    pub timeout: Option<u64>,
+
+    /// Whether the dirs on other devices than the start dir are skipped.
+    pub one_file_system: bool,
+
+    /// Whether the listings of the dirs are cached on the disk.
+    pub listing_cache: bool,
+
+    /// The dirs indexed by the daemon.
+    pub daemon_roots: Vec<PathBuf>,
+
+    /// Whether the dirs are listed from the prebuilt index.
+    pub index: bool,
+
+    /// The dirs indexed by `_kn index build`.
+    pub index_roots: Vec<PathBuf>,
+
+    /// Whether `.zip`, `.tar`, `.tar.gz` and `.tgz` archives are searched as
+    /// if they were dirs.
+    pub archives: bool,
+}
+
+impl Config {
+    /// Reads and parses the config file.
+    pub fn load() -> Result<Self, Error> {
+        Self::parse(&Self::read()?)
+    }
+
+    /// Reads the contents of the config file. A missing file is empty.
+    pub fn read() -> Result<String, Error> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(String::new()),
+        };
+
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(contents),
+            Err(error) if error.kind() == io::ErrorKind::NotFound =>
+                Ok(String::new()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Parses the contents of the config file.
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Returns the location of the config file.
+    fn path() -> Option<PathBuf> {
+        match env::var_os(CONFIG_ENV_VAR) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::config_dir()
+                .map(|config_dir| config_dir.join("kn").join("config.toml")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_matcher_kind() {
+        assert_variant!("default".parse(), Ok(MatcherKind::Default));
+        assert_variant!("levenshtein".parse(), Ok(MatcherKind::Levenshtein));
+        assert_variant!(
+            "smith-waterman".parse(),
+            Ok(MatcherKind::SmithWaterman)
+        );
+        assert_variant!("exact".parse(), Ok(MatcherKind::Exact));
+        assert_variant!(
+            "fuzzy".parse::<MatcherKind>(),
+            Err(Error::InvalidMatcher(_))
+        );
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.matcher, MatcherKind::Default);
+        assert!(!config.typos);
+        assert!(!config.join_args);
+        assert!(config.tiebreak.is_empty());
+        assert_eq!(config.beam_width, None);
+        assert_eq!(config.max_dirs, None);
+        assert_eq!(config.threads, None);
+        assert_eq!(config.timeout, None);
+        assert!(!config.one_file_system);
+        assert!(!config.listing_cache);
+        assert!(config.daemon_roots.is_empty());
+        assert!(!config.index);
+        assert!(config.index_roots.is_empty());
+        assert!(!config.archives);
+
+        let config = Config::parse("matcher = \"smith-waterman\"").unwrap();
+        assert_eq!(config.matcher, MatcherKind::SmithWaterman);
+
+        let config = Config::parse("typos = true").unwrap();
+        assert!(config.typos);
+
+        let config = Config::parse("join_args = true").unwrap();
+        assert!(config.join_args);
+
+        let config =
+            Config::parse("tiebreak = [\"mtime\", \"name-length\"]").unwrap();
+        assert_eq!(config.tiebreak, [Tiebreak::Mtime, Tiebreak::NameLength]);
+
+        let config =
+            Config::parse("beam_width = 100\nmax_dirs = 10000").unwrap();
+        assert_eq!(config.beam_width, Some(100));
+        assert_eq!(config.max_dirs, Some(10000));
+
+        let config = Config::parse("threads = 1\ntimeout = 500").unwrap();
+        assert_eq!(config.threads, Some(1));
+        assert_eq!(config.timeout, Some(500));
+
+        let config = Config::parse("one_file_system = true").unwrap();
+        assert!(config.one_file_system);
+
+        let config = Config::parse("listing_cache = true").unwrap();
+        assert!(config.listing_cache);
+
+        let config = Config::parse("daemon_roots = [\"/src\"]").unwrap();
+        assert_eq!(config.daemon_roots, [PathBuf::from("/src")]);
+
+        let config = Config::parse("index = true").unwrap();
+        assert!(config.index);
+
+        let config = Config::parse("index_roots = [\"/src\"]").unwrap();
+        assert_eq!(config.index_roots, [PathBuf::from("/src")]);
+
+        let config = Config::parse("archives = true").unwrap();
+        assert!(config.archives);
+
+        assert_variant!(
+            Config::parse("matcher = \"fuzzy\""),
+            Err(Error::Config(_))
+        );
+        assert_variant!(Config::parse("unknown = 1"), Err(Error::Config(_)));
+    }
+}
@@ -0,0 +1,771 @@
+//! The `daemon` subcommand.
+//!
+//! The daemon keeps an index of the dirs under the configured roots in memory,
+//! keeps it up to date by watching the filesystem and answers queries sent by
+//! `_kn query` over a Unix socket. If the daemon is not running, `_kn query`
+//! reads the dirs from the disk itself.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Read, Write},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+    thread,
+    time::Duration,
+};
+
+use notify::{
+    event::ModifyKind,
+    Event,
+    EventKind,
+    RecommendedWatcher,
+    RecursiveMode,
+    Watcher,
+};
+
+use kn::{DirReader, Entry, Error, Found, Lister};
+
+use crate::{
+    args::{self, Subcommand},
+    cache,
+    config::Config,
+    index::Listing,
+};
+
+/// The time `_kn query` waits for the daemon before reading the dirs itself.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Separates the parts of requests and responses.
+const SEPARATOR: u8 = 0;
+
+/// Response status of a found path.
+const FOUND: u8 = b'f';
+
+/// Response status of a found path when the search was truncated.
+const FOUND_TRUNCATED: u8 = b't';
+
+/// Response status of an error.
+const FAILED: u8 = b'e';
+
+/// The listings of all dirs under the roots.
+#[derive(Debug, Default)]
+pub struct Index {
+    roots: Vec<PathBuf>,
+    listings: BTreeMap<PathBuf, Listing>,
+
+    /// Whether the dirs on other devices than their root are skipped.
+    one_file_system: bool,
+
+    /// Whether the roots are watched. If not, a listing is used only if the
+    /// dir has not been modified since it's been read.
+    watched: bool,
+
+    /// The filesystem events received while the index is being built, read
+    /// once it's built. `None` when the index is built.
+    queued: Option<Vec<notify::Result<Event>>>,
+}
+
+/// The listings read after a filesystem event. They're read without blocking
+/// queries and applied to the [`Index`](Index) at once.
+#[derive(Debug, Default)]
+struct Changes {
+    /// Whether the listings replace all listings in the index.
+    rebuilt: bool,
+
+    /// The dirs removed along with their descendants.
+    removed: Vec<PathBuf>,
+
+    /// The listings inserted after the removal.
+    inserted: Vec<(PathBuf, Listing)>,
+}
+
+impl Index {
+    /// Creates the empty index of the dirs under the roots, to be built with
+    /// [`build`](build). Until then, the filesystem events are queued and the
+    /// dirs are read from the disk. If `one_file_system` is `true`, the dirs on
+    /// other devices than their root are not indexed.
+    pub fn new(roots: Vec<PathBuf>, one_file_system: bool) -> Self {
+        Self {
+            roots,
+            listings: BTreeMap::new(),
+            one_file_system,
+            watched: true,
+            queued: Some(vec![]),
+        }
+    }
+
+    /// Builds the index of the dirs under the roots, without watching them.
+    pub fn build(roots: Vec<PathBuf>, one_file_system: bool) -> Self {
+        let index = RwLock::new(Self::new(roots, one_file_system));
+        build(&index);
+
+        index.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Reads every dir under the roots again.
+    fn rebuild(&self) -> Changes {
+        let mut changes = Changes {
+            rebuilt: true,
+            ..Changes::default()
+        };
+
+        for root in &self.roots {
+            self.read_tree(root, &mut changes);
+        }
+
+        changes
+    }
+
+    /// Reads the dir and its descendants. Symlinks are not followed.
+    fn read_tree(&self, dir: &Path, changes: &mut Changes) {
+        let device = self.root_device(dir);
+        let mut dirs = vec![dir.to_path_buf()];
+
+        while let Some(dir) = dirs.pop() {
+            if device.is_some() && DirReader.device(&dir) != device {
+                continue;
+            }
+
+            let listing = Listing::read(&dir, cache::RACY_MARGIN);
+            dirs.extend(
+                listing
+                    .entries
+                    .iter()
+                    .filter(|entry| !entry.is_symlink && !entry.is_file)
+                    .map(|entry| dir.join(&entry.name)),
+            );
+            changes.inserted.push((dir, listing));
+        }
+    }
+
+    /// Returns the device of the innermost root containing the dir if the dirs
+    /// on other devices are skipped.
+    fn root_device(&self, dir: &Path) -> Option<u64> {
+        if !self.one_file_system {
+            return None;
+        }
+
+        self.roots
+            .iter()
+            .filter(|root| dir.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .and_then(|root| DirReader.device(root))
+    }
+
+    /// Reads the changes after the path has been created, removed or renamed.
+    fn read_update(&self, path: &Path, changes: &mut Changes) {
+        if let Some(parent) = path.parent() {
+            if self.listings.contains_key(parent) {
+                let listing = Listing::read(parent, cache::RACY_MARGIN);
+                changes.inserted.push((parent.to_path_buf(), listing));
+            }
+        }
+
+        changes.removed.push(path.to_path_buf());
+
+        let is_dir =
+            fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir());
+        let is_under_root =
+            self.roots.iter().any(|root| path.starts_with(root));
+
+        if is_dir && is_under_root {
+            self.read_tree(path, changes);
+        }
+    }
+
+    /// Reads the changes after a filesystem event or after some events have
+    /// been lost.
+    fn read_result(&self, event: &notify::Result<Event>) -> Changes {
+        match event {
+            Ok(event) => self.read_event(event),
+            Err(_) => self.rebuild(),
+        }
+    }
+
+    /// Reads the changes after a filesystem event.
+    fn read_event(&self, event: &Event) -> Changes {
+        if event.need_rescan() {
+            return self.rebuild();
+        }
+
+        let mut changes = Changes::default();
+
+        match event.kind {
+            EventKind::Any
+            | EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Name(_) | ModifyKind::Any)
+            | EventKind::Other =>
+                for path in &event.paths {
+                    self.read_update(path, &mut changes);
+                },
+            EventKind::Access(_) | EventKind::Modify(_) => {}
+        }
+
+        changes
+    }
+
+    /// Applies the changes read from the disk.
+    fn apply(&mut self, changes: Changes) {
+        if changes.rebuilt {
+            self.listings.clear();
+        }
+
+        for dir in &changes.removed {
+            self.remove_tree(dir);
+        }
+
+        self.listings.extend(changes.inserted);
+    }
+
+    /// Removes the listings of the dir and its descendants.
+    fn remove_tree(&mut self, dir: &Path) {
+        let removed = self
+            .listings
+            .range(dir.to_path_buf()..)
+            .map(|(path, _)| path)
+            .take_while(|path| path.starts_with(dir))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for path in removed {
+            self.listings.remove(&path);
+        }
+    }
+}
+
+/// Lists the dirs from the [`Index`](Index). The dirs outside of the roots are
+/// read from the disk, as are the modified dirs if the roots are not watched.
+#[derive(Clone, Debug)]
+pub struct IndexLister(Arc<RwLock<Index>>);
+
+impl Lister for IndexLister {
+    fn list(&self, dir: &Path) -> Vec<Entry> {
+        let entries = match self.0.read() {
+            Ok(index) => index
+                .listings
+                .get(dir)
+                .filter(|listing| {
+                    index.watched || listing.is_fresh(dir, cache::RACY_MARGIN)
+                })
+                .map(|listing| listing.entries.clone()),
+            Err(_) => None,
+        };
+
+        entries.unwrap_or_else(|| cache::list_dir(dir))
+    }
+}
+
+/// Returns the location of the daemon's socket, `kn/daemon.sock` in the
+/// [runtime dir](dirs::runtime_dir) or, if there is none, in the
+/// [cache dir](dirs::cache_dir).
+pub fn socket_path() -> Option<PathBuf> {
+    dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .map(|dir| dir.join("kn").join("daemon.sock"))
+}
+
+/// The `daemon` subcommand.
+///
+/// Indexes the roots (or, if there are none, the `daemon_roots` from the
+/// config file) and answers queries until it's killed. With `one_file_system`
+/// in the config file, the dirs on other devices than their root are not
+/// indexed.
+pub fn daemon(roots: Vec<PathBuf>) -> Result<(), Error> {
+    let config = Config::load()?;
+    let roots = if roots.is_empty() {
+        config.daemon_roots
+    } else {
+        roots
+    };
+
+    if roots.is_empty() {
+        return Err(Error::NoDaemonRoots);
+    }
+
+    let roots = roots
+        .iter()
+        .map(fs::canonicalize)
+        .collect::<Result<Vec<_>, _>>()?;
+    let socket_path = socket_path().ok_or(Error::NoDaemonSocket)?;
+    let listener = bind(&socket_path)?;
+
+    // The roots are watched before they're read, so that the dirs changed
+    // while the index is being built are read again.
+    let index = Arc::new(RwLock::new(Index::new(
+        roots.clone(),
+        config.one_file_system,
+    )));
+    let _watcher = match watch(&roots, Arc::clone(&index)) {
+        Ok(watcher) => Some(watcher),
+        // E.g. the limit of inotify watches has been reached.
+        Err(error) => {
+            eprintln!(
+                "{} The listings will be checked against the dirs' \
+                 modification times instead.",
+                error
+            );
+
+            if let Ok(mut index) = index.write() {
+                index.watched = false;
+            }
+
+            None
+        }
+    };
+    build(&index);
+    accept(listener, IndexLister(index));
+
+    Ok(())
+}
+
+/// Answers queries until the daemon is killed. Every client is served on its
+/// own thread, so a slow query doesn't delay the others.
+fn accept(listener: UnixListener, lister: IndexLister) {
+    // A failed connection must not stop the daemon.
+    for stream in listener.incoming().flatten() {
+        let lister = lister.clone();
+
+        thread::spawn(move || serve(stream, &lister));
+    }
+}
+
+/// Binds the socket, removing the socket of a daemon which is not running
+/// anymore.
+fn bind(socket_path: &Path) -> Result<UnixListener, Error> {
+    if socket_path.exists() {
+        if UnixStream::connect(socket_path).is_ok() {
+            return Err(Error::DaemonRunning);
+        }
+
+        fs::remove_file(socket_path)?;
+    }
+
+    if let Some(socket_dir) = socket_path.parent() {
+        fs::create_dir_all(socket_dir)?;
+    }
+
+    Ok(UnixListener::bind(socket_path)?)
+}
+
+/// Builds the index created with [`Index::new`](Index::new). The dirs are read
+/// under the read lock, which doesn't block queries. Then the events queued
+/// meanwhile are read under the write lock, so that no later event is applied
+/// before them.
+fn build(index: &RwLock<Index>) {
+    let changes = match index.read() {
+        Ok(index) => index.rebuild(),
+        Err(_) => return,
+    };
+    finish_build(index, changes);
+}
+
+/// Applies the listings read by [`build`](build) and reads the queued events.
+fn finish_build(index: &RwLock<Index>, changes: Changes) {
+    let mut index = match index.write() {
+        Ok(index) => index,
+        Err(_) => return,
+    };
+    index.apply(changes);
+
+    for event in index.queued.take().unwrap_or_default() {
+        let changes = index.read_result(&event);
+        index.apply(changes);
+    }
+}
+
+/// Updates the index after the filesystem event or queues the event if the
+/// index is being built. The changed dirs are read under the read lock, which
+/// doesn't block queries, and the index is locked for writing only while
+/// they're inserted.
+fn handle(index: &RwLock<Index>, event: notify::Result<Event>) {
+    let is_building = index.read().is_ok_and(|index| index.queued.is_some());
+
+    if is_building {
+        if let Ok(mut index) = index.write() {
+            if let Some(queued) = &mut index.queued {
+                queued.push(event);
+
+                return;
+            }
+        }
+    }
+
+    let changes = match index.read() {
+        Ok(index) => index.read_result(&event),
+        Err(_) => return,
+    };
+
+    if let Ok(mut index) = index.write() {
+        index.apply(changes);
+    }
+}
+
+/// Watches the roots and updates the index on every change.
+fn watch(
+    roots: &[PathBuf],
+    index: Arc<RwLock<Index>>,
+) -> Result<RecommendedWatcher, Error> {
+    let mut watcher =
+        notify::recommended_watcher(move |event| handle(&index, event))
+            .map_err(|error| Error::Watch(error.to_string()))?;
+
+    for root in roots {
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .map_err(|error| Error::Watch(error.to_string()))?;
+    }
+
+    Ok(watcher)
+}
+
+/// Answers a single query. The args are parsed with the client's config file
+/// and relative paths are resolved against the client's current dir, so the
+/// daemon's own environment doesn't affect the result. A timed-out search is
+/// cancelled, only the listing in progress is completed in the background.
+fn serve(mut stream: UnixStream, lister: &IndexLister) -> io::Result<()> {
+    let mut request = vec![];
+    stream.read_to_end(&mut request)?;
+
+    let mut parts = decode(&request).into_iter();
+    let cwd = PathBuf::from(parts.next().unwrap_or_default());
+    let config = parts.next().unwrap_or_default();
+    let found = if cwd.is_absolute() {
+        args::parse_args_from(parts.collect(), || {
+            Config::parse(&config.to_string_lossy())
+        })
+    } else {
+        Err(Error::Daemon("Expected an absolute current dir.".into()))
+    }
+    .and_then(|subcommand| match subcommand {
+        Subcommand::Query(mut args) => {
+            args.options = args.options.current_dir(cwd);
+
+            crate::run_query(args, Some(Arc::new(lister.clone())))
+        }
+        _ => Err(Error::Daemon("Expected a query.".into())),
+    });
+
+    stream.write_all(&encode_response(&found))
+}
+
+/// Sends the query to the daemon. The args are passed to `_kn` without the name
+/// of the binary. Returns `None` if the daemon is not running or doesn't
+/// respond in time.
+pub fn ask(args: Vec<OsString>) -> Option<Result<Found, Error>> {
+    let socket_path = socket_path()?;
+
+    if !socket_path.exists() {
+        return None;
+    }
+
+    let cwd = env::current_dir().ok()?;
+    let config = Config::read().ok()?;
+    let request = encode_request(cwd.into_os_string(), config.into(), args)?;
+
+    let mut stream = UnixStream::connect(socket_path).ok()?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+    stream.write_all(&request).ok()?;
+    stream.shutdown(std::net::Shutdown::Write).ok()?;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response).ok()?;
+
+    decode_response(&response)
+}
+
+/// Encodes the request, the current dir and the contents of the config file
+/// followed by the args. Returns `None` if any of them contains the separator.
+fn encode_request(
+    cwd: OsString,
+    config: OsString,
+    args: Vec<OsString>,
+) -> Option<Vec<u8>> {
+    let mut request = vec![];
+
+    for part in vec![cwd, config].into_iter().chain(args) {
+        let part = part.into_vec();
+
+        if part.contains(&SEPARATOR) {
+            return None;
+        }
+
+        request.extend(part);
+        request.push(SEPARATOR);
+    }
+
+    Some(request)
+}
+
+/// Splits the request into its parts.
+fn decode(request: &[u8]) -> Vec<OsString> {
+    request
+        .strip_suffix(&[SEPARATOR])
+        .unwrap_or(request)
+        .split(|byte| *byte == SEPARATOR)
+        .map(|part| OsString::from_vec(part.to_vec()))
+        .collect()
+}
+
+/// Encodes the result of a query.
+fn encode_response(found: &Result<Found, Error>) -> Vec<u8> {
+    match found {
+        Ok(Found { path, truncated }) => {
+            let status = if *truncated { FOUND_TRUNCATED } else { FOUND };
+
+            Some(status)
+                .into_iter()
+                .chain(path.as_os_str().as_bytes().iter().copied())
+                .collect()
+        }
+        Err(error) => Some(FAILED)
+            .into_iter()
+            .chain(error.to_string().into_bytes())
+            .collect(),
+    }
+}
+
+/// Decodes the result of a query. Returns `None` if the response is malformed.
+fn decode_response(response: &[u8]) -> Option<Result<Found, Error>> {
+    let (status, rest) = response.split_first()?;
+    let path = || PathBuf::from(OsString::from_vec(rest.to_vec()));
+
+    match *status {
+        FOUND => Some(Ok(Found {
+            path: path(),
+            truncated: false,
+        })),
+        FOUND_TRUNCATED => Some(Ok(Found {
+            path: path(),
+            truncated: true,
+        })),
+        FAILED => Some(Err(Error::Daemon(
+            String::from_utf8_lossy(rest).into_owned(),
+        ))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(index: &Index, dir: &Path) -> Option<Vec<String>> {
+        let mut names = index
+            .listings
+            .get(dir)?
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        Some(names)
+    }
+
+    fn update(index: &mut Index, path: &Path) {
+        let mut changes = Changes::default();
+        index.read_update(path, &mut changes);
+        index.apply(changes);
+    }
+
+    #[test]
+    fn test_index() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        fs::create_dir_all(root.join("foo/bar")).unwrap();
+        fs::create_dir_all(root.join("foo-baz")).unwrap();
+
+        let mut index = Index::build(vec![root.clone()], false);
+        assert_eq!(names(&index, &root).unwrap(), ["foo", "foo-baz"]);
+        assert_eq!(names(&index, &root.join("foo")).unwrap(), ["bar"]);
+        assert_eq!(names(&index, &root.join("foo/bar")).unwrap(), [""; 0]);
+
+        fs::create_dir_all(root.join("foo/qux/quux")).unwrap();
+        update(&mut index, &root.join("foo/qux"));
+        assert_eq!(names(&index, &root.join("foo")).unwrap(), ["bar", "qux"]);
+        assert_eq!(names(&index, &root.join("foo/qux")).unwrap(), ["quux"]);
+
+        fs::remove_dir_all(root.join("foo")).unwrap();
+        update(&mut index, &root.join("foo"));
+        assert_eq!(names(&index, &root).unwrap(), ["foo-baz"]);
+        assert_eq!(names(&index, &root.join("foo")), None);
+        assert_eq!(names(&index, &root.join("foo/qux/quux")), None);
+        assert_eq!(names(&index, &root.join("foo-baz")).unwrap(), [""; 0]);
+    }
+
+    #[test]
+    fn test_index_changed_during_build() {
+        use notify::event::{CreateKind, RemoveKind};
+
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        fs::create_dir_all(root.join("foo/bar")).unwrap();
+
+        let index = RwLock::new(Index::new(vec![root.clone()], false));
+        let changes = index.read().unwrap().rebuild();
+
+        // The dirs change after they've been read, the events are queued.
+        fs::create_dir(root.join("baz")).unwrap();
+        fs::remove_dir_all(root.join("foo")).unwrap();
+        for (kind, path) in [
+            (EventKind::Create(CreateKind::Folder), root.join("baz")),
+            (EventKind::Remove(RemoveKind::Folder), root.join("foo/bar")),
+            (EventKind::Remove(RemoveKind::Folder), root.join("foo")),
+        ] {
+            handle(&index, Ok(Event::new(kind).add_path(path)));
+        }
+        assert!(index.read().unwrap().listings.is_empty());
+
+        finish_build(&index, changes);
+        let index = index.into_inner().unwrap();
+        assert!(index.queued.is_none());
+        assert_eq!(names(&index, &root).unwrap(), ["baz"]);
+        assert_eq!(names(&index, &root.join("baz")).unwrap(), [""; 0]);
+        assert_eq!(names(&index, &root.join("foo")), None);
+        assert_eq!(names(&index, &root.join("foo/bar")), None);
+    }
+
+    #[test]
+    fn test_index_one_file_system() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        fs::create_dir_all(root.join("foo/bar")).unwrap();
+
+        // Every dir is on the same device as the root.
+        let index = Index::build(vec![root.clone()], true);
+        assert_eq!(names(&index, &root.join("foo")).unwrap(), ["bar"]);
+        assert_eq!(
+            index.root_device(&root.join("foo")),
+            DirReader.device(&root)
+        );
+        assert_eq!(index.root_device(Path::new("/")), None);
+        assert_eq!(
+            Index::build(vec![root.clone()], false).root_device(&root),
+            None
+        );
+    }
+
+    #[test]
+    fn test_index_lister_fallback() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::create_dir(outside.path().join("foo")).unwrap();
+
+        let index = Index::build(vec![root.path().into()], false);
+        let lister = IndexLister(Arc::new(RwLock::new(index)));
+        let entries = lister.list(outside.path());
+
+        assert_variant!(entries.as_slice(), [Entry { name, .. }] if name == "foo");
+    }
+
+    #[test]
+    fn test_index_lister_unwatched() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("foo")).unwrap();
+
+        let mut index = Index::build(vec![root.path().into()], false);
+        let listing = index.listings.get_mut(root.path()).unwrap();
+        listing.entries[0].name = "stale".into();
+        let index = Arc::new(RwLock::new(index));
+        let lister = IndexLister(Arc::clone(&index));
+
+        assert_variant!(lister.list(root.path()).as_slice(), [Entry { name, .. }] if name == "stale");
+
+        // The dir has just been modified, so its listing is not trusted.
+        index.write().unwrap().watched = false;
+        assert_variant!(lister.list(root.path()).as_slice(), [Entry { name, .. }] if name == "foo");
+    }
+
+    #[test]
+    fn test_protocol() {
+        let request = encode_request(
+            "/some/dir".into(),
+            "typos = true\n".into(),
+            vec!["query".into(), "--abbr".into(), "foo bar".into()],
+        )
+        .unwrap();
+        assert_eq!(
+            decode(&request),
+            ["/some/dir", "typos = true\n", "query", "--abbr", "foo bar"]
+        );
+        assert_eq!(encode_request("a\0b".into(), "".into(), vec![]), None);
+
+        let found = Ok(Found {
+            path: "/some/dir".into(),
+            truncated: true,
+        });
+        assert_variant!(
+            decode_response(&encode_response(&found)),
+            Some(Ok(Found { path, truncated: true })) if path == Path::new("/some/dir")
+        );
+
+        let failed = Err(Error::PathNotFound);
+        assert_variant!(
+            decode_response(&encode_response(&failed)),
+            Some(Err(Error::Daemon(message))) if message == "Path not found."
+        );
+        assert_variant!(decode_response(b""), None);
+    }
+
+    #[test]
+    fn test_serve() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        fs::create_dir_all(root.join("foo/bar")).unwrap();
+        let socket_dir = tempfile::tempdir().unwrap();
+        let socket_path = socket_dir.path().join("daemon.sock");
+
+        let listener = bind(&socket_path).unwrap();
+        let lister = IndexLister(Arc::new(RwLock::new(Index::build(
+            vec![root.clone()],
+            false,
+        ))));
+        thread::spawn(move || accept(listener, lister));
+
+        // A client which hasn't sent its request yet doesn't block the others.
+        let _pending = UnixStream::connect(&socket_path).unwrap();
+        let send = |cwd: &Path, config: &str| {
+            let request = encode_request(
+                cwd.into(),
+                config.into(),
+                vec!["query".into(), "--abbr".into(), "fo/ba".into()],
+            )
+            .unwrap();
+            let mut stream = UnixStream::connect(&socket_path).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(60)))
+                .unwrap();
+            stream.write_all(&request).unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut response = vec![];
+            stream.read_to_end(&mut response).unwrap();
+
+            decode_response(&response)
+        };
+
+        // The abbreviation is relative to the client's current dir and parsed
+        // with the client's config.
+        assert_variant!(
+            send(&root, ""),
+            Some(Ok(Found { path, truncated: false })) if path == root.join("foo/bar")
+        );
+        assert_variant!(
+            send(&root, "matcher = \"exact\""),
+            Some(Err(Error::Daemon(_)))
+        );
+        assert_variant!(
+            send(Path::new("relative"), ""),
+            Some(Err(Error::Daemon(_)))
+        );
+        assert_variant!(bind(&socket_path), Err(Error::DaemonRunning));
+    }
+}
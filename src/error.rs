@@ -4,6 +4,7 @@ use thiserror::Error;
 
 /// Error.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Wrapper around [`std::io::Error`](std::io::Error).
     #[error("IO error `{0}`.")]
@@ -17,6 +18,17 @@ pub enum Error {
     #[error("Path not found.")]
     PathNotFound,
 
+    /// Path not found but the search was truncated.
+    #[error(
+        "Path not found. The search was truncated, consider increasing \
+         `beam_width` or `max_dirs`."
+    )]
+    SearchTruncated,
+
+    /// Path not found before the timeout.
+    #[error("Path not found before the timeout.")]
+    Timeout,
+
     /// An invalid arg value.
     #[error("Value of arg `{0}` is invalid.")]
     InvalidArgValue(String),
@@ -25,7 +37,77 @@ pub enum Error {
     #[error("Args error: `{0}`.")]
     Args(#[from] pico_args::Error),
 
+    /// Invalid glob or regex in the abbr.
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    /// Unknown matcher name.
+    #[error("Unknown matcher `{0}`.")]
+    InvalidMatcher(String),
+
+    /// Unknown tiebreaker name.
+    #[error("Unknown tiebreaker `{0}`.")]
+    InvalidTiebreak(String),
+
+    /// Invalid config file.
+    #[error("Invalid config file `{0}`.")]
+    Config(#[from] toml::de::Error),
+
     /// Unexpected abbr component.
     #[error("Unexpected abbr component `{0}`.")]
     UnexpectedAbbrComponent(String),
+
+    /// No dirs to index.
+    #[error(
+        "No dirs to index. Pass them with `--root` or set `daemon_roots` in \
+         the config file."
+    )]
+    NoDaemonRoots,
+
+    /// The location of the daemon's socket could not be determined.
+    #[error("Could not determine the location of the daemon's socket.")]
+    NoDaemonSocket,
+
+    /// Another daemon is already running.
+    #[error("The daemon is already running.")]
+    DaemonRunning,
+
+    /// The daemon is not supported on this platform.
+    #[error("The daemon is only supported on Unix.")]
+    DaemonUnsupported,
+
+    /// Error returned by the daemon.
+    #[error("{0}")]
+    Daemon(String),
+
+    /// Error watching the filesystem.
+    #[error("Could not watch the filesystem: {0}")]
+    Watch(String),
+
+    /// No dirs to index.
+    #[error(
+        "No dirs to index. Pass them to `_kn index build` or set \
+         `index_roots` in the config file."
+    )]
+    NoIndexRoots,
+
+    /// The location of the index could not be determined.
+    #[error("Could not determine the location of the index.")]
+    NoIndexPath,
+
+    /// The index has not been built.
+    #[error("The index does not exist. Build it with `_kn index build`.")]
+    NoIndex,
+
+    /// The index file is malformed.
+    #[error("Invalid index file. Rebuild it with `_kn index build`.")]
+    InvalidIndex,
+
+    /// The archive could not be read.
+    #[error("Could not read the archive: {0}")]
+    Archive(String),
+
+    /// The location of extracted archives could not be determined.
+    #[error("Could not determine where to extract archives.")]
+    NoArchiveDir,
 }
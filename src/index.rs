@@ -0,0 +1,696 @@
+//! The `index` subcommand and the prebuilt index of dirs.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use kn::{DirReader, Entry, Error, Lister};
+use memmap2::Mmap;
+
+use crate::{
+    cache::{self, format_listing, parse_listing, settled_mtime},
+    config::Config,
+};
+
+/// The first line of the index file, changed whenever the format changes.
+const HEADER: &str = "kn index 2";
+
+/// The length of a record of the table of listings' offsets, including the
+/// newline.
+const RECORD_LEN: usize = 34;
+
+/// Stands in for the mtime of a dir modified within the racy margin. Such a
+/// listing is never trusted.
+const UNSETTLED_MTIME: &str = "-";
+
+/// A dir's listing in an index, stored with the dir's mtime.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Listing {
+    mtime: String,
+
+    /// The dir's children.
+    pub entries: Vec<Entry>,
+}
+
+impl Listing {
+    /// Reads the dir's listing.
+    pub fn read(dir: &Path, racy_margin: Duration) -> Self {
+        Self {
+            mtime: settled_mtime(dir, racy_margin)
+                .unwrap_or_else(|| UNSETTLED_MTIME.to_string()),
+            entries: cache::list_dir(dir),
+        }
+    }
+
+    /// Checks if the dir has not been modified since it's been read.
+    pub fn is_fresh(&self, dir: &Path, racy_margin: Duration) -> bool {
+        settled_mtime(dir, racy_margin).as_ref() == Some(&self.mtime)
+    }
+}
+
+/// The listings of all dirs under the roots, stored in a single file. Built
+/// and updated by the `index` subcommand, queries read it with
+/// [`IndexReader`](IndexReader).
+///
+/// Every listing is stored with the dir's mtime. A dir modified after the
+/// index has been built is stale; its listing is read from the disk instead,
+/// so the index never returns removed dirs. Listings of recently modified dirs
+/// are never trusted (see [`RACY_MARGIN`](cache::RACY_MARGIN)). Symlinks are
+/// not followed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IndexFile {
+    roots: Vec<PathBuf>,
+    listings: BTreeMap<PathBuf, Listing>,
+    racy_margin: Duration,
+}
+
+/// What [`IndexFile::update`](IndexFile::update) has done.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UpdateStats {
+    /// The number of dirs in the index.
+    pub indexed: usize,
+
+    /// The number of dirs read again, because they were stale or new.
+    pub read: usize,
+}
+
+impl IndexFile {
+    /// Returns the default location of the index, `kn/index` in the
+    /// [cache dir](dirs::cache_dir) (usually `~/.cache/kn/index`).
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::cache_dir().map(|cache_dir| cache_dir.join("kn").join("index"))
+    }
+
+    /// Builds the index of the dirs under the roots. If `one_file_system` is
+    /// `true`, the dirs on other devices than their root are not indexed.
+    pub fn build(
+        roots: Vec<PathBuf>,
+        racy_margin: Duration,
+        one_file_system: bool,
+    ) -> Self {
+        let mut index = Self {
+            roots,
+            listings: BTreeMap::new(),
+            racy_margin,
+        };
+        index.update(one_file_system);
+
+        index
+    }
+
+    /// Reads again the listings of the dirs modified since the index has been
+    /// built, adds new dirs and drops removed ones. If `one_file_system` is
+    /// `true`, the dirs on other devices than their root are dropped.
+    pub fn update(&mut self, one_file_system: bool) -> UpdateStats {
+        let mut old_listings = std::mem::take(&mut self.listings);
+        let mut dirs = self
+            .roots
+            .iter()
+            .map(|root| {
+                let device = if one_file_system {
+                    DirReader.device(root)
+                } else {
+                    None
+                };
+
+                (root.clone(), device)
+            })
+            .collect::<Vec<_>>();
+        let mut read = 0;
+
+        while let Some((dir, device)) = dirs.pop() {
+            // Roots may be nested.
+            if self.listings.contains_key(&dir)
+                || (device.is_some() && DirReader.device(&dir) != device)
+            {
+                continue;
+            }
+
+            let mtime = settled_mtime(&dir, self.racy_margin);
+            let listing = match (old_listings.remove(&dir), mtime) {
+                (Some(listing), Some(mtime)) if listing.mtime == mtime =>
+                    listing,
+                (_, mtime) => {
+                    read += 1;
+
+                    Listing {
+                        mtime: mtime
+                            .unwrap_or_else(|| UNSETTLED_MTIME.to_string()),
+                        entries: cache::list_dir(&dir),
+                    }
+                }
+            };
+
+            dirs.extend(
+                listing
+                    .entries
+                    .iter()
+                    .filter(|entry| !entry.is_symlink && !entry.is_file)
+                    .map(|entry| (dir.join(&entry.name), device)),
+            );
+            self.listings.insert(dir, listing);
+        }
+
+        UpdateStats {
+            indexed: self.listings.len(),
+            read,
+        }
+    }
+
+    /// Reads the index from the file.
+    pub fn read(path: &Path, racy_margin: Duration) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)?;
+
+        Self::parse(&contents, racy_margin).ok_or(Error::InvalidIndex)
+    }
+
+    /// Writes the index to the file.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        // Queries never read a partial index.
+        cache::create_atomically(path, |tmp_path| {
+            fs::write(tmp_path, self.format())
+        })?;
+
+        Ok(())
+    }
+
+    /// Formats the index. The header is followed by the offset and the number
+    /// of the records of the table of listings, the roots, one per line, and
+    /// an empty line. Then come the listings formatted with
+    /// [`format_listing`](cache::format_listing), sorted by the dirs' paths,
+    /// and the table, a record with the offset and the length of each listing.
+    /// Numbers are stored as 16 hex digits. Paths which can't be stored on a
+    /// single line are skipped, such dirs are read from the disk instead.
+    fn format(&self) -> String {
+        let mut contents = format!("{}\n{}", HEADER, format_record(0, 0));
+
+        for root in &self.roots {
+            if let Some(root) =
+                root.to_str().filter(|root| !root.contains('\n'))
+            {
+                contents.push_str(root);
+                contents.push('\n');
+            }
+        }
+
+        contents.push('\n');
+
+        let mut table = String::new();
+        let mut n_listings = 0;
+
+        for (dir, listing) in &self.listings {
+            if let Some(block) =
+                format_listing(dir, &listing.mtime, &listing.entries)
+            {
+                table.push_str(&format_record(contents.len(), block.len()));
+                contents.push_str(&block);
+                n_listings += 1;
+            }
+        }
+
+        let table_offset = contents.len();
+        contents.push_str(&table);
+        contents.replace_range(
+            HEADER.len() + 1..HEADER.len() + 1 + RECORD_LEN,
+            &format_record(table_offset, n_listings),
+        );
+
+        contents
+    }
+
+    /// Parses the index formatted with [`format`](Self::format). Returns
+    /// `None` if it's malformed.
+    fn parse(contents: &str, racy_margin: Duration) -> Option<Self> {
+        let mut lines = contents.lines();
+
+        if lines.next()? != HEADER {
+            return None;
+        }
+
+        let (table_offset, n_listings) = parse_record(lines.next()?)?;
+        let roots = lines
+            .take_while(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect();
+        let listings = contents
+            .get(table_offset..)?
+            .lines()
+            .map(|record| {
+                let (offset, len) = parse_record(record)?;
+                let block = contents.get(offset..offset.checked_add(len)?)?;
+                let (dir, mtime, entries) = parse_listing(block)?;
+                let listing = Listing {
+                    mtime: mtime.to_string(),
+                    entries,
+                };
+
+                Some((PathBuf::from(dir), listing))
+            })
+            .collect::<Option<BTreeMap<_, _>>>()?;
+
+        (listings.len() == n_listings).then_some(Self {
+            roots,
+            listings,
+            racy_margin,
+        })
+    }
+}
+
+/// Formats a record of the table of listings, or the location of the table.
+fn format_record(offset: usize, len: usize) -> String {
+    format!("{:016x} {:016x}\n", offset, len)
+}
+
+/// Parses a record formatted with [`format_record`](format_record), without
+/// the newline.
+fn parse_record(record: &str) -> Option<(usize, usize)> {
+    let (offset, len) = record.split_once(' ')?;
+
+    Some((
+        usize::from_str_radix(offset, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Lists the dirs from the index file without reading all of it.
+///
+/// The file is mapped into memory and a listing is parsed only when the dir is
+/// listed. It's found with a binary search over the table of listings, sorted
+/// by the dirs' paths. Like with [`IndexFile`](IndexFile), stale listings are
+/// read from the disk instead, which takes a single `stat` per dir.
+#[derive(Debug)]
+pub struct IndexReader {
+    contents: Mmap,
+    table_offset: usize,
+    n_listings: usize,
+    racy_margin: Duration,
+}
+
+impl IndexReader {
+    /// Maps the index file into memory, parsing only its header.
+    pub fn open(path: &Path, racy_margin: Duration) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // SAFETY: The index is replaced atomically, never modified in place.
+        let contents = unsafe { Mmap::map(&file)? };
+        let header_len = HEADER.len() + 1;
+
+        if contents.get(..header_len)
+            != Some(format!("{}\n", HEADER).as_bytes())
+        {
+            return Err(Error::InvalidIndex);
+        }
+
+        let (table_offset, n_listings) =
+            get_str(&contents, header_len, RECORD_LEN - 1)
+                .and_then(parse_record)
+                .filter(|&(table_offset, n_listings)| {
+                    n_listings
+                        .checked_mul(RECORD_LEN)
+                        .and_then(|len| len.checked_add(table_offset))
+                        == Some(contents.len())
+                })
+                .ok_or(Error::InvalidIndex)?;
+
+        Ok(Self {
+            contents,
+            table_offset,
+            n_listings,
+            racy_margin,
+        })
+    }
+
+    /// Finds the dir's listing. Returns `None` if the dir is not indexed or the
+    /// file is malformed.
+    fn find(&self, dir: &Path) -> Option<Listing> {
+        let (mut low, mut high) = (0, self.n_listings);
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let record_offset = self.table_offset + middle * RECORD_LEN;
+            let record =
+                get_str(&self.contents, record_offset, RECORD_LEN - 1)?;
+            let (offset, len) = parse_record(record)?;
+            let block = get_str(&self.contents, offset, len)?;
+
+            match Path::new(block.lines().next()?).cmp(dir) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => {
+                    let (_, mtime, entries) = parse_listing(block)?;
+
+                    return Some(Listing {
+                        mtime: mtime.to_string(),
+                        entries,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Returns `len` bytes at the offset if they are UTF-8.
+fn get_str(contents: &[u8], offset: usize, len: usize) -> Option<&str> {
+    let bytes = contents.get(offset..offset.checked_add(len)?)?;
+
+    std::str::from_utf8(bytes).ok()
+}
+
+impl Lister for IndexReader {
+    fn list(&self, dir: &Path) -> Vec<Entry> {
+        match self.find(dir) {
+            Some(listing) if listing.is_fresh(dir, self.racy_margin) =>
+                listing.entries,
+            _ => cache::list_dir(dir),
+        }
+    }
+}
+
+/// The `index build` subcommand.
+///
+/// Indexes the roots (or, if there are none, the `index_roots` from the config
+/// file) and writes the index to the file. With `one_file_system` in the config
+/// file, the dirs on other devices than their root are not indexed.
+pub fn build(roots: Vec<PathBuf>, path: &Path) -> Result<UpdateStats, Error> {
+    let config = Config::load()?;
+    let roots = if roots.is_empty() {
+        config.index_roots
+    } else {
+        roots
+    };
+
+    if roots.is_empty() {
+        return Err(Error::NoIndexRoots);
+    }
+
+    let roots = roots
+        .iter()
+        .map(fs::canonicalize)
+        .collect::<Result<Vec<_>, _>>()?;
+    let index =
+        IndexFile::build(roots, cache::RACY_MARGIN, config.one_file_system);
+    index.write(path)?;
+
+    Ok(UpdateStats {
+        indexed: index.listings.len(),
+        read: index.listings.len(),
+    })
+}
+
+/// The `index update` subcommand.
+///
+/// Reads again only the dirs modified since the index has been built or
+/// updated.
+pub fn update(path: &Path) -> Result<UpdateStats, Error> {
+    if !path.exists() {
+        return Err(Error::NoIndex);
+    }
+
+    let one_file_system = Config::load()?.one_file_system;
+    let mut index = IndexFile::read(path, cache::RACY_MARGIN)?;
+    let stats = index.update(one_file_system);
+    index.write(path)?;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use kn::{Query, QueryOptions};
+
+    use super::*;
+
+    fn names(entries: &[Entry]) -> Vec<&str> {
+        let mut names = entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        names
+    }
+
+    #[test]
+    fn test_format() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("foo/bar")).unwrap();
+        fs::create_dir_all(root.path().join("baz qux")).unwrap();
+
+        let index =
+            IndexFile::build(vec![root.path().into()], Duration::ZERO, false);
+        assert_eq!(index.listings.len(), 4);
+        assert_eq!(
+            IndexFile::parse(&index.format(), Duration::ZERO),
+            Some(index)
+        );
+
+        assert_eq!(
+            IndexFile::parse("kn index 0\n/dir\n", Duration::ZERO),
+            None
+        );
+        assert_eq!(
+            IndexFile::parse("kn index 2\n\n/dir\n", Duration::ZERO),
+            None
+        );
+    }
+
+    /// Modifies the dir and sets its mtime back, so that it changes even if
+    /// the clock's resolution is coarse.
+    fn modify<F>(dir: &Path, modify: F)
+    where
+        F: FnOnce(),
+    {
+        let mtime = fs::metadata(dir).unwrap().modified().unwrap();
+        modify();
+        File::open(dir)
+            .unwrap()
+            .set_modified(mtime - Duration::from_secs(1))
+            .unwrap();
+    }
+
+    /// Writes the index to a temp file and opens it.
+    fn open(index: &IndexFile) -> (tempfile::TempDir, IndexReader) {
+        let index_dir = tempfile::tempdir().unwrap();
+        let path = index_dir.path().join("index");
+        index.write(&path).unwrap();
+        let reader = IndexReader::open(&path, index.racy_margin).unwrap();
+
+        (index_dir, reader)
+    }
+
+    #[test]
+    fn test_reader() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in &["foo/bar", "foo bar", "foo-bar", "baz/qux/quux"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+
+        let index =
+            IndexFile::build(vec![root.path().into()], Duration::ZERO, false);
+        let (_index_dir, reader) = open(&index);
+
+        for (dir, listing) in &index.listings {
+            assert_eq!(reader.find(dir).as_ref(), Some(listing));
+        }
+
+        assert_eq!(reader.find(&root.path().join("missing")), None);
+        assert_eq!(reader.find(Path::new("/")), None);
+        assert_eq!(
+            names(&reader.list(root.path())),
+            ["baz", "foo", "foo bar", "foo-bar"]
+        );
+    }
+
+    #[test]
+    fn test_reader_invalid() {
+        let root = tempfile::tempdir().unwrap();
+        let index =
+            IndexFile::build(vec![root.path().into()], Duration::ZERO, false);
+        let path = root.path().join("index");
+        index.write(&path).unwrap();
+        let contents = fs::read(&path).unwrap();
+
+        fs::write(&path, &contents[..contents.len() - 1]).unwrap();
+        assert_variant!(
+            IndexReader::open(&path, Duration::ZERO).err(),
+            Some(Error::InvalidIndex)
+        );
+
+        fs::write(&path, "kn index 1\n").unwrap();
+        assert_variant!(
+            IndexReader::open(&path, Duration::ZERO).err(),
+            Some(Error::InvalidIndex)
+        );
+    }
+
+    #[test]
+    fn test_staleness() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("foo/bar")).unwrap();
+
+        let mut index =
+            IndexFile::build(vec![root.path().into()], Duration::ZERO, false);
+        let missing = root.path().join("missing");
+        index
+            .listings
+            .get_mut(root.path())
+            .unwrap()
+            .entries
+            .push(Entry {
+                name: "missing".into(),
+                is_symlink: false,
+                is_file: false,
+            });
+        let (_index_dir, reader) = open(&index);
+        assert_eq!(names(&reader.list(root.path())), ["foo", "missing"]);
+        assert!(reader.list(&missing).is_empty());
+
+        modify(root.path(), || {
+            fs::remove_dir_all(root.path().join("foo")).unwrap()
+        });
+        assert!(reader.list(root.path()).is_empty());
+    }
+
+    #[test]
+    fn test_normalized_start_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("foo")).unwrap();
+        fs::create_dir_all(root.join("bar/baz")).unwrap();
+
+        let mut index =
+            IndexFile::build(vec![root.clone()], Duration::ZERO, false);
+        // Only the index has the entry.
+        index
+            .listings
+            .get_mut(&root.join("bar"))
+            .unwrap()
+            .entries
+            .push(Entry {
+                name: "missing".into(),
+                is_symlink: false,
+                is_file: false,
+            });
+        let (_index_dir, reader) = open(&index);
+        let options = QueryOptions::default()
+            .current_dir(root.join("foo"))
+            .start_dir("../bar")
+            .lister(Arc::new(reader));
+
+        assert_eq!(
+            Query::with_options("mis", options).find().unwrap().path,
+            root.join("foo/../bar/missing")
+        );
+    }
+
+    #[test]
+    fn test_staleness_racy() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("foo")).unwrap();
+
+        let mut index = IndexFile::build(
+            vec![root.path().into()],
+            cache::RACY_MARGIN,
+            false,
+        );
+        assert_eq!(index.listings[root.path()].mtime, UNSETTLED_MTIME);
+
+        // The dir has just been modified.
+        index.listings.get_mut(root.path()).unwrap().entries.clear();
+        let (_index_dir, reader) = open(&index);
+        assert_eq!(names(&reader.list(root.path())), ["foo"]);
+    }
+
+    #[test]
+    fn test_update() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("foo/bar")).unwrap();
+        fs::create_dir_all(root.path().join("baz")).unwrap();
+
+        let mut index =
+            IndexFile::build(vec![root.path().into()], Duration::ZERO, false);
+        assert_eq!(
+            index.update(false),
+            UpdateStats {
+                indexed: 4,
+                read: 0,
+            }
+        );
+
+        modify(root.path(), || {
+            fs::remove_dir_all(root.path().join("foo")).unwrap()
+        });
+        modify(&root.path().join("baz"), || {
+            fs::create_dir(root.path().join("baz/qux")).unwrap()
+        });
+
+        assert_eq!(
+            index.update(false),
+            UpdateStats {
+                indexed: 3,
+                read: 3,
+            }
+        );
+        assert!(!index.listings.contains_key(&root.path().join("foo/bar")));
+        assert_eq!(
+            names(&index.listings[&root.path().join("baz")].entries),
+            ["qux"]
+        );
+    }
+
+    #[test]
+    fn test_one_file_system() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("foo/bar")).unwrap();
+
+        // Every dir is on the same device as the root.
+        let mut index =
+            IndexFile::build(vec![root.path().into()], Duration::ZERO, true);
+        assert_eq!(index.listings.len(), 3);
+        assert_eq!(index.update(true).indexed, 3);
+    }
+
+    #[test]
+    fn test_build_and_update() {
+        let root = tempfile::tempdir().unwrap();
+        let index_dir = tempfile::tempdir().unwrap();
+        let path = index_dir.path().join("kn").join("index");
+        fs::create_dir(root.path().join("foo")).unwrap();
+
+        assert_variant!(update(&path), Err(Error::NoIndex));
+
+        let stats = build(vec![root.path().into()], &path).unwrap();
+        assert_eq!(stats.indexed, 2);
+
+        let index = IndexFile::read(&path, Duration::ZERO).unwrap();
+        assert_eq!(index.roots, [fs::canonicalize(root.path()).unwrap()]);
+        assert_eq!(update(&path).unwrap().indexed, 2);
+
+        assert!(IndexReader::open(&path, Duration::ZERO).is_ok());
+
+        fs::write(&path, "garbage").unwrap();
+        assert_variant!(
+            IndexFile::read(&path, Duration::ZERO),
+            Err(Error::InvalidIndex)
+        );
+        assert_variant!(
+            IndexReader::open(&path, Duration::ZERO),
+            Err(Error::InvalidIndex)
+        );
+
+        // An index in the previous format.
+        fs::write(&path, format!("kn index 1\n{}\n", root.path().display()))
+            .unwrap();
+        assert_variant!(
+            IndexReader::open(&path, Duration::ZERO),
+            Err(Error::InvalidIndex)
+        );
+    }
+}
@@ -7,15 +7,15 @@ use crate::args::Shell;
 /// Prints a shell script for initializing `kn`. The script
 /// can be configured. The `init` subcommand takes an arg `--shell`,
 /// specifying the used shell, and a flag `--exclude-old-pwd` which
-/// enables excluding the previous location from the search (only if there
-/// are other matching dirs).
+/// enables avoiding the previous location (it's returned only if there are
+/// no other matching dirs).
 pub fn init(shell: Shell, exclude_old_pwd: bool) -> String {
     match shell {
         Shell::Fish => {
             let query_command = if exclude_old_pwd {
-                "_kn query --exclude \"$dirprev[-1]\" --abbr \"$argv\""
+                "_kn query --avoid \"$dirprev[-1]\" $__kn_args"
             } else {
-                "_kn query --abbr \"$argv\""
+                "_kn query $__kn_args"
             };
 
             format!(
@@ -25,9 +25,9 @@ pub fn init(shell: Shell, exclude_old_pwd: bool) -> String {
         }
         Shell::Zsh => {
             let query_command = if exclude_old_pwd {
-                "_kn query --exclude \"${OLDPWD}\" --abbr \"$@\""
+                "_kn query --avoid \"${OLDPWD}\" \"${__kn_args[@]}\""
             } else {
-                "_kn query --abbr \"$@\""
+                "_kn query \"${__kn_args[@]}\""
             };
 
             format!(
@@ -37,9 +37,9 @@ pub fn init(shell: Shell, exclude_old_pwd: bool) -> String {
         }
         Shell::Bash => {
             let query_command = if exclude_old_pwd {
-                "_kn query --exclude \"${OLDPWD}\" --abbr \"$@\""
+                "_kn query --avoid \"${OLDPWD}\" \"${__kn_args[@]}\""
             } else {
-                "_kn query --abbr \"$@\""
+                "_kn query \"${__kn_args[@]}\""
             };
 
             format!(
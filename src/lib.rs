@@ -0,0 +1,55 @@
+#![warn(missing_docs)]
+
+//! Navigate by typing abbreviations of paths.
+//!
+//! The library behind `kn`, an alternative to `cd`. A [`Query`](Query) finds
+//! the dirs matching an abbreviation like `pr/kn`, ordered by the
+//! [`Congruence`](Congruence)'s of their names with the [`Abbr`](Abbr)'s.
+//!
+//! ```no_run
+//! use kn::{Limits, Query, QueryOptions};
+//!
+//! let options = QueryOptions::new().limit(5).limits(Limits {
+//!     max_dirs: Some(10_000),
+//!     ..Limits::default()
+//! });
+//!
+//! for found in Query::with_options("pr/kn", options).matches()? {
+//!     println!("{}", found.path.display());
+//! }
+//! # Ok::<(), kn::Error>(())
+//! ```
+
+#[macro_use]
+mod utils;
+mod abbr;
+mod error;
+mod listing;
+mod matcher;
+mod query;
+mod tiebreak;
+
+pub use crate::{
+    abbr::{Abbr, Congruence},
+    error::Error,
+    listing::{DirReader, Entry, Lister, MemoryTree, Metadata},
+    matcher::{
+        DefaultMatcher,
+        Distance,
+        ExactMatcher,
+        LevenshteinMatcher,
+        Matcher,
+        SmithWatermanMatcher,
+    },
+    query::{
+        Exclusion,
+        Found,
+        Hidden,
+        Limits,
+        Match,
+        Matches,
+        Query,
+        QueryOptions,
+    },
+    tiebreak::Tiebreak,
+};
@@ -0,0 +1,331 @@
+//! Listings of dirs.
+
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A dir's child, that is a dir, a symlink or a regular file. Files are
+/// skipped by the search, unless a lister searches them as dirs (e.g.
+/// archives), listing them as dirs. Only children with UTF-8 names are listed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// The name of the child.
+    pub name: String,
+
+    /// Whether the child is a symlink.
+    pub is_symlink: bool,
+
+    /// Whether the child is a regular file.
+    pub is_file: bool,
+}
+
+/// The times of a dir used by the [`Tiebreak`](crate::Tiebreak)'s. A time is
+/// `None` if it's unknown.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metadata {
+    /// The time of the last modification.
+    pub modified: Option<SystemTime>,
+
+    /// The time of the last access.
+    pub accessed: Option<SystemTime>,
+}
+
+/// A source of dirs' listings, the filesystem as seen by the search.
+///
+/// Besides reading the disk, listings may come from a cache, an index or a
+/// tree kept in memory (see [`MemoryTree`](MemoryTree)). Implement it to
+/// search other trees, e.g. archives or remote filesystems.
+pub trait Lister: Send + Sync {
+    /// Lists the dir's children. Files may be left out, since they are skipped
+    /// anyway. Returns an empty listing if the dir can't be read.
+    fn list(&self, dir: &Path) -> Vec<Entry>;
+
+    /// Checks if the path is a dir. Symlinks are resolved. Defaults to
+    /// [`Path::is_dir`](Path::is_dir).
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    /// Returns the dir's metadata. Symlinks are resolved. Defaults to the
+    /// times from [`fs::metadata`](std::fs::metadata).
+    fn metadata(&self, path: &Path) -> Metadata {
+        match std::fs::metadata(path) {
+            Ok(metadata) => Metadata {
+                modified: metadata.modified().ok(),
+                accessed: metadata.accessed().ok(),
+            },
+            Err(_) => Metadata::default(),
+        }
+    }
+
+    /// Returns the ID of the device containing the path or `None` if it's
+    /// unknown. Symlinks are not resolved, so that automounts are not
+    /// triggered. Defaults to the device from
+    /// [`symlink_metadata`](std::fs::symlink_metadata) on Unix and `None` on
+    /// other platforms.
+    fn device(&self, path: &Path) -> Option<u64> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            std::fs::symlink_metadata(path)
+                .ok()
+                .map(|metadata| metadata.dev())
+        }
+
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+
+            None
+        }
+    }
+}
+
+/// Lists dirs with [`read_dir`](std::fs::read_dir). Dirs, symlinks and regular
+/// files are listed.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DirReader;
+
+impl Lister for DirReader {
+    fn list(&self, dir: &Path) -> Vec<Entry> {
+        let reader = match dir.read_dir() {
+            Ok(reader) => reader,
+            Err(_) => return vec![],
+        };
+
+        reader
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let file_type = entry.file_type().ok()?;
+
+                if file_type.is_dir()
+                    || file_type.is_symlink()
+                    || file_type.is_file()
+                {
+                    Some(Entry {
+                        name: entry.file_name().into_string().ok()?,
+                        is_symlink: file_type.is_symlink(),
+                        is_file: file_type.is_file(),
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// The maximum number of symlinks resolved in a single path by
+/// [`MemoryTree`](MemoryTree), so that cycles end.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// A tree of dirs and symlinks kept in memory, e.g. in tests.
+///
+/// Parent dirs are added along with their children. Symlinks may point to
+/// absolute paths or paths relative to their parent dir and may dangle. Paths
+/// are added as given, they shouldn't go through symlinks.
+///
+/// ```
+/// use kn::{Lister, MemoryTree};
+/// use std::path::Path;
+///
+/// let tree = MemoryTree::new()
+///     .dir("/home/projects/kn")
+///     .symlink("/home/kn", "projects/kn");
+///
+/// assert!(tree.is_dir(Path::new("/home/kn")));
+/// assert_eq!(tree.list(Path::new("/home")).len(), 2);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryTree {
+    dirs: BTreeMap<PathBuf, Vec<Entry>>,
+    symlinks: BTreeMap<PathBuf, PathBuf>,
+}
+
+impl MemoryTree {
+    /// Creates an empty tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the dir and its parents.
+    pub fn dir<P>(mut self, path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let path = self.add(path.as_ref(), false);
+        self.dirs.entry(path).or_default();
+
+        self
+    }
+
+    /// Adds the symlink pointing to the target and the symlink's parents.
+    pub fn symlink<P, T>(mut self, path: P, target: T) -> Self
+    where
+        P: AsRef<Path>,
+        T: Into<PathBuf>,
+    {
+        let path = self.add(path.as_ref(), true);
+        self.symlinks.insert(path, target.into());
+
+        self
+    }
+
+    /// Adds the path's components to their parents' listings. Returns the
+    /// path with `.` components skipped.
+    fn add(&mut self, path: &Path, is_symlink: bool) -> PathBuf {
+        let mut components = path.components().peekable();
+        let mut parent = PathBuf::new();
+
+        while let Some(component) = components.next() {
+            if let Component::Normal(name) = component {
+                let name = name.to_string_lossy().into_owned();
+                let entries = self.dirs.entry(parent.clone()).or_default();
+
+                if !entries.iter().any(|entry| entry.name == name) {
+                    entries.push(Entry {
+                        name,
+                        is_symlink: is_symlink && components.peek().is_none(),
+                        is_file: false,
+                    });
+                }
+            }
+
+            parent.push(component);
+        }
+
+        parent
+    }
+
+    /// Resolves the symlinks and the `..` components in the path. Returns
+    /// `None` if there are too many symlinks.
+    fn resolve(&self, path: &Path, hops: &mut usize) -> Option<PathBuf> {
+        let mut resolved = PathBuf::new();
+
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                component => {
+                    resolved.push(component);
+
+                    if let Some(target) = self.symlinks.get(&resolved) {
+                        *hops += 1;
+                        if *hops > MAX_SYMLINK_HOPS {
+                            return None;
+                        }
+
+                        resolved.pop();
+                        resolved =
+                            self.resolve(&resolved.join(target), hops)?;
+                    }
+                }
+            }
+        }
+
+        Some(resolved)
+    }
+}
+
+impl Lister for MemoryTree {
+    fn list(&self, dir: &Path) -> Vec<Entry> {
+        self.resolve(dir, &mut 0)
+            .and_then(|dir| self.dirs.get(&dir).cloned())
+            .unwrap_or_default()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.resolve(path, &mut 0)
+            .is_some_and(|path| self.dirs.contains_key(&path))
+    }
+
+    /// The times of the dirs in the tree are unknown.
+    fn metadata(&self, _path: &Path) -> Metadata {
+        Metadata::default()
+    }
+
+    /// The tree is not on any device.
+    fn device(&self, _path: &Path) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    fn sorted_names(mut entries: Vec<Entry>) -> Vec<String> {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn test_dir_reader() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("foo")).unwrap();
+        fs::create_dir(root.path().join("bar")).unwrap();
+        fs::write(root.path().join("file"), "").unwrap();
+
+        assert_eq!(
+            sorted_names(DirReader.list(root.path())),
+            ["bar", "file", "foo"]
+        );
+        assert!(DirReader
+            .list(root.path())
+            .iter()
+            .any(|entry| entry.name == "file" && entry.is_file));
+        assert!(DirReader.list(&root.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_memory_tree() {
+        let tree = MemoryTree::new()
+            .dir("/foo/bar")
+            .dir("/foo/./baz")
+            .symlink("/foo/link", "bar")
+            .symlink("/abs", "/foo/link")
+            .symlink("/dangling", "/missing")
+            .symlink("/loop", "/loop");
+
+        assert_eq!(
+            sorted_names(tree.list(Path::new("/"))),
+            ["abs", "dangling", "foo", "loop"]
+        );
+        assert_eq!(
+            tree.list(Path::new("/foo")),
+            [
+                Entry {
+                    name: "bar".into(),
+                    is_symlink: false,
+                    is_file: false,
+                },
+                Entry {
+                    name: "baz".into(),
+                    is_symlink: false,
+                    is_file: false,
+                },
+                Entry {
+                    name: "link".into(),
+                    is_symlink: true,
+                    is_file: false,
+                },
+            ]
+        );
+
+        assert!(tree.is_dir(Path::new("/foo/baz")));
+        assert!(tree.is_dir(Path::new("/abs")));
+        assert!(tree.is_dir(Path::new("/foo/link/../baz")));
+        assert!(!tree.is_dir(Path::new("/dangling")));
+        assert!(!tree.is_dir(Path::new("/loop")));
+        assert!(!tree.is_dir(Path::new("/foo/qux")));
+        assert!(tree.list(Path::new("/loop")).is_empty());
+    }
+}
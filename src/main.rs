@@ -2,18 +2,37 @@
 
 //! Alternative to `cd`. Navigate by typing abbreviations of paths.
 
-use std::process::exit;
+use std::{path::Path, process::exit, sync::Arc};
 
 #[macro_use]
 pub mod utils;
-pub mod abbr;
+pub mod archive;
 pub mod args;
-pub mod error;
-
+pub mod cache;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod index;
 pub mod init;
-pub mod query;
 
-use crate::{args::Subcommand, error::Error};
+use kn::{
+    DirReader,
+    Error,
+    ExactMatcher,
+    Found,
+    LevenshteinMatcher,
+    Lister,
+    Query,
+    SmithWatermanMatcher,
+};
+
+use crate::{
+    archive::ArchiveLister,
+    args::{QueryArgs, Subcommand},
+    cache::ListingCache,
+    config::MatcherKind,
+    index::{IndexFile, IndexReader},
+};
 
 /// A wrapper around the main function.
 fn main() {
@@ -43,15 +62,138 @@ fn _main() -> Result<(), Error> {
 
             Ok(())
         }
-        Subcommand::Query { abbr, excluded } => {
-            match query::query(&abbr, excluded) {
+        Subcommand::Query(args) => {
+            let archives = args.archives;
+            #[cfg(unix)]
+            let found = match daemon::ask(std::env::args_os().skip(1).collect())
+            {
+                Some(found) => found,
+                None => run_query(args, None),
+            };
+            #[cfg(not(unix))]
+            let found = run_query(args, None);
+
+            match found {
                 Err(error) => Err(error),
-                Ok(path) => {
+                Ok(Found { path, truncated }) => {
+                    if truncated {
+                        eprintln!(
+                            "The search was truncated, a better match may \
+                             exist."
+                        );
+                    }
+                    // A path in an archive is printed extracted, so that the
+                    // shell functions can enter it.
+                    let path = if archives && !path.is_dir() {
+                        let dir = archive::default_dir()
+                            .ok_or(Error::NoArchiveDir)?;
+                        archive::extract(&path, &dir)?
+                    } else {
+                        path
+                    };
                     println!("{}", path.display());
 
                     Ok(())
                 }
             }
         }
+        Subcommand::Daemon { roots } => {
+            #[cfg(unix)]
+            {
+                daemon::daemon(roots)
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = roots;
+
+                Err(Error::DaemonUnsupported)
+            }
+        }
+        Subcommand::IndexBuild { roots } => {
+            let path = IndexFile::default_path().ok_or(Error::NoIndexPath)?;
+            let stats = index::build(roots, &path)?;
+            println!("Indexed {} dirs.", stats.indexed);
+
+            Ok(())
+        }
+        Subcommand::IndexUpdate => {
+            let path = IndexFile::default_path().ok_or(Error::NoIndexPath)?;
+            let stats = index::update(&path)?;
+            println!(
+                "Indexed {} dirs, {} of them read again.",
+                stats.indexed, stats.read
+            );
+
+            Ok(())
+        }
+        Subcommand::Extract { path } => {
+            let dir = archive::default_dir().ok_or(Error::NoArchiveDir)?;
+            println!("{}", archive::extract(&path, &dir)?.display());
+
+            Ok(())
+        }
+    }
+}
+
+/// Opens the index or, if it's invalid, warns about it on stderr and returns
+/// `None`.
+fn open_index(path: &Path) -> Option<IndexReader> {
+    match IndexReader::open(path, cache::RACY_MARGIN) {
+        Ok(index) => Some(index),
+        Err(err) => {
+            eprintln!("{}", err);
+            None
+        }
+    }
+}
+
+/// Runs the `query` subcommand. The dirs are listed by the provided
+/// [`Lister`](Lister) or, if there's none, from the prebuilt index, the listing
+/// cache or the disk. If enabled, archives are listed as well.
+pub fn run_query(
+    args: QueryArgs,
+    lister: Option<Arc<dyn Lister>>,
+) -> Result<Found, Error> {
+    let QueryArgs {
+        abbrs,
+        matcher,
+        options,
+        listing_cache,
+        index,
+        archives,
+    } = args;
+    let listing_cache = listing_cache.then(ListingCache::default_dir).flatten();
+    // Queries fall back to the disk until the index is built or if it's
+    // invalid, e.g. truncated or written by another version.
+    let index = index
+        .then(IndexFile::default_path)
+        .flatten()
+        .filter(|path| path.exists())
+        .and_then(|path| open_index(&path));
+    let lister = lister.unwrap_or_else(|| match (index, listing_cache) {
+        (Some(index), _) => Arc::new(index),
+        (None, Some(dir)) =>
+            Arc::new(ListingCache::new(dir, cache::RACY_MARGIN)),
+        (None, None) => Arc::new(DirReader),
+    });
+    let lister: Arc<dyn Lister> = if archives {
+        Arc::new(ArchiveLister::new(lister))
+    } else {
+        lister
+    };
+    let options = options.lister(lister);
+
+    match matcher {
+        MatcherKind::Default => Query::with_args(abbrs, options).find(),
+        MatcherKind::Levenshtein => Query::with_args(
+            abbrs,
+            options.matcher(LevenshteinMatcher::default()),
+        )
+        .find(),
+        MatcherKind::SmithWaterman =>
+            Query::with_args(abbrs, options.matcher(SmithWatermanMatcher))
+                .find(),
+        MatcherKind::Exact =>
+            Query::with_args(abbrs, options.matcher(ExactMatcher)).find(),
     }
 }
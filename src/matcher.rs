@@ -0,0 +1,497 @@
+//! Matchers.
+
+use std::{cell::RefCell, cmp::Reverse, fmt::Debug};
+
+use crate::abbr::{self, Congruence};
+
+/// A way of comparing abbreviations with dir names.
+///
+/// A matcher maps a pair of a literal abbreviation and a component to a score.
+/// Scores are ordered so that the **lower** score means the better match.
+/// Wildcards and other non-literal parts of [`Abbr`](crate::abbr::Abbr) are
+/// handled by [`Abbr::compare_with`](crate::abbr::Abbr::compare_with) and
+/// get the matcher's [`best_score`](Matcher::best_score).
+pub trait Matcher {
+    /// The strength of the match. Lower is better.
+    type Score: Ord + Clone + Debug;
+
+    /// Returns the score of a component matched by a wildcard.
+    fn best_score(&self) -> Self::Score;
+
+    /// Compares a component against a literal abbreviation. The abbreviation
+    /// is already lowercase.
+    fn compare_literal(
+        &self,
+        literal: &str,
+        component: &str,
+    ) -> Option<Self::Score>;
+
+    /// Returns whether the score belongs to a fallback match, one which is
+    /// only used if there are no other matches on the same level of the
+    /// search.
+    fn is_fallback(&self, _score: &Self::Score) -> bool {
+        false
+    }
+}
+
+/// The matcher described in [`Congruence`'s docs](Congruence).
+///
+/// Components are matched if the abbreviation is their subsequence. The
+/// matches are divided into tiers — complete, prefix, word boundary and
+/// subsequence matches. If `typos` is enabled, components within a small edit
+/// distance from the abbreviation are matched as a fallback.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DefaultMatcher {
+    /// Whether to match components with [`Typo`](Congruence::Typo)'s.
+    pub typos: bool,
+}
+
+impl Matcher for DefaultMatcher {
+    type Score = Congruence;
+
+    fn best_score(&self) -> Self::Score {
+        Congruence::Complete
+    }
+
+    fn compare_literal(
+        &self,
+        literal: &str,
+        component: &str,
+    ) -> Option<Self::Score> {
+        abbr::compare_literal(literal, component, self.typos)
+    }
+
+    fn is_fallback(&self, score: &Self::Score) -> bool {
+        matches!(score, Congruence::Typo(_))
+    }
+}
+
+/// Matches only components equal to the abbreviation, ignoring the case.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ExactMatcher;
+
+impl Matcher for ExactMatcher {
+    type Score = ();
+
+    fn best_score(&self) -> Self::Score {}
+
+    fn compare_literal(
+        &self,
+        literal: &str,
+        component: &str,
+    ) -> Option<Self::Score> {
+        if !literal.is_empty() && component.eq_ignore_ascii_case(literal) {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+/// Tolerates typos in the abbreviation.
+///
+/// The abbreviation is compared with each prefix of the component using the
+/// [optimal string alignment distance](https://en.wikipedia.org/wiki/Damerau%E2%80%93Levenshtein_distance#Optimal_string_alignment_distance),
+/// that is, insertions, deletions, substitutions and transpositions of
+/// adjacent characters count as single edits. The abbreviation must contain
+/// at least one character which is not edited, so `x` does not match every
+/// dir.
+#[derive(Copy, Clone, Debug)]
+pub struct LevenshteinMatcher {
+    /// The maximal number of edits.
+    pub max_edits: u32,
+}
+
+impl Default for LevenshteinMatcher {
+    fn default() -> Self {
+        Self { max_edits: 2 }
+    }
+}
+
+/// The score of [`LevenshteinMatcher`](LevenshteinMatcher).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Distance {
+    /// The number of edits turning the abbreviation into a prefix of the
+    /// component.
+    pub edits: u32,
+
+    /// The number of characters of the component after that prefix.
+    pub unmatched: u32,
+}
+
+impl Matcher for LevenshteinMatcher {
+    type Score = Distance;
+
+    fn best_score(&self) -> Self::Score {
+        Distance {
+            edits: 0,
+            unmatched: 0,
+        }
+    }
+
+    fn compare_literal(
+        &self,
+        literal: &str,
+        component: &str,
+    ) -> Option<Self::Score> {
+        if literal.is_empty() || component.is_empty() {
+            return None;
+        }
+
+        let max_edits = self.max_edits.min(literal.chars().count() as u32 - 1);
+
+        Scratch::with(|scratch| {
+            scratch.set_component(component);
+            let Scratch {
+                chars, distances, ..
+            } = scratch;
+
+            osa_distances(literal, chars, distances)
+                .iter()
+                .enumerate()
+                .map(|(prefix_len, &edits)| Distance {
+                    edits,
+                    unmatched: (chars.len() - prefix_len) as u32,
+                })
+                .filter(|distance| distance.edits <= max_edits)
+                .min()
+        })
+    }
+}
+
+/// Scores components like [`fzf`](https://github.com/junegunn/fzf) does.
+///
+/// The abbreviation must be a subsequence of the component. The matched
+/// characters are aligned using a variant of the
+/// [Smith-Waterman algorithm](https://en.wikipedia.org/wiki/Smith%E2%80%93Waterman_algorithm)
+/// which rewards consecutive characters and characters beginning words and
+/// penalizes gaps. The score is wrapped in [`Reverse`](std::cmp::Reverse)
+/// so that the highest score comes first.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SmithWatermanMatcher;
+
+const SCORE_MATCH: i64 = 16;
+const SCORE_GAP_START: i64 = -3;
+const SCORE_GAP_EXTENSION: i64 = -1;
+const BONUS_BOUNDARY: i64 = SCORE_MATCH / 2;
+const BONUS_CONSECUTIVE: i64 = -(SCORE_GAP_START + SCORE_GAP_EXTENSION);
+const BONUS_FIRST_CHAR_MULTIPLIER: i64 = 2;
+
+impl Matcher for SmithWatermanMatcher {
+    type Score = Reverse<i64>;
+
+    fn best_score(&self) -> Self::Score {
+        Reverse(i64::MAX)
+    }
+
+    fn compare_literal(
+        &self,
+        literal: &str,
+        component: &str,
+    ) -> Option<Self::Score> {
+        Scratch::with(|scratch| {
+            scratch.set_component(component);
+            abbr::find_word_boundaries(component, &mut scratch.boundaries);
+
+            let Scratch {
+                chars,
+                boundaries,
+                scores: [previous_row, current_row],
+                ..
+            } = scratch;
+
+            smith_waterman_score(
+                literal,
+                chars,
+                boundaries,
+                previous_row,
+                current_row,
+            )
+        })
+    }
+}
+
+/// Aligns the literal with the lowercase characters of the component using the
+/// rows as buffers and returns the best score of
+/// [`SmithWatermanMatcher`](SmithWatermanMatcher).
+fn smith_waterman_score(
+    literal: &str,
+    component: &[char],
+    boundaries: &[bool],
+    previous_row: &mut Vec<Option<i64>>,
+    current_row: &mut Vec<Option<i64>>,
+) -> Option<Reverse<i64>> {
+    let bonus = |x: usize| if boundaries[x] { BONUS_BOUNDARY } else { 0 };
+
+    // `previous_row[x]` holds the best score of matching the abbreviation's
+    // characters so far with the last one matched at `x`.
+    previous_row.clear();
+
+    for (y, literal_char) in literal.chars().enumerate() {
+        current_row.clear();
+        current_row.resize(component.len(), None);
+
+        for (x, &component_char) in component.iter().enumerate() {
+            if component_char != literal_char {
+                continue;
+            }
+
+            current_row[x] = if y == 0 {
+                Some(SCORE_MATCH + bonus(x) * BONUS_FIRST_CHAR_MULTIPLIER)
+            } else {
+                previous_row[..x]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(previous_x, score)| {
+                        let gap = (x - previous_x - 1) as i64;
+                        let gap_score = if gap == 0 {
+                            BONUS_CONSECUTIVE
+                        } else {
+                            SCORE_GAP_START + SCORE_GAP_EXTENSION * (gap - 1)
+                        };
+
+                        score.map(|score| score + gap_score)
+                    })
+                    .max()
+                    .map(|score| score + SCORE_MATCH + bonus(x))
+            };
+        }
+
+        std::mem::swap(previous_row, current_row);
+    }
+
+    previous_row.iter().flatten().max().copied().map(Reverse)
+}
+
+/// Computes the [optimal string alignment distance](https://en.wikipedia.org/wiki/Damerau%E2%80%93Levenshtein_distance#Optimal_string_alignment_distance)
+/// between `a` and each prefix of `b`.
+///
+/// The `n`-th element of the returned slice is the distance between `a` and
+/// the first `n` characters of `b`. The rows of the matrix are kept in `rows`.
+pub(crate) fn osa_distances<'r>(
+    a: &str,
+    b: &[char],
+    rows: &'r mut [Vec<u32>; 3],
+) -> &'r [u32] {
+    // Rows of the matrix for the prefixes of `a` of length `y - 2`, `y - 1`
+    // and `y`.
+    let [before_previous_row, previous_row, current_row] = rows;
+    previous_row.clear();
+    previous_row.extend(0..=b.len() as u32);
+    let mut previous_a_char = None;
+
+    for (y, a_char) in a.chars().enumerate() {
+        current_row.clear();
+        current_row.resize(b.len() + 1, y as u32 + 1);
+
+        for (x, &b_char) in b.iter().enumerate() {
+            let substitution_cost = if a_char == b_char { 0 } else { 1 };
+
+            let mut distance = (previous_row[x] + substitution_cost)
+                .min(previous_row[x + 1] + 1)
+                .min(current_row[x] + 1);
+
+            let is_transposition = x > 0
+                && a_char == b[x - 1]
+                && previous_a_char == Some(b_char)
+                && a_char != b_char;
+            if is_transposition {
+                distance = distance.min(before_previous_row[x - 1] + 1);
+            }
+
+            current_row[x + 1] = distance;
+        }
+
+        std::mem::swap(before_previous_row, previous_row);
+        std::mem::swap(previous_row, current_row);
+        previous_a_char = Some(a_char);
+    }
+
+    previous_row
+}
+
+/// Buffers reused between comparisons, so that comparing a component doesn't
+/// allocate once they've grown to fit the longest component.
+#[derive(Debug, Default)]
+pub(crate) struct Scratch {
+    /// The lowercase component.
+    pub(crate) lowercase: String,
+
+    /// The characters of the lowercase component.
+    pub(crate) chars: Vec<char>,
+
+    /// Whether each of the component's characters begins a word.
+    pub(crate) boundaries: Vec<bool>,
+
+    /// The rows of the matrix of [`osa_distances`](osa_distances).
+    pub(crate) distances: [Vec<u32>; 3],
+
+    /// The rows of the matrix of gaps between word boundaries.
+    pub(crate) gaps: [Vec<Option<u32>>; 2],
+
+    /// The rows of the matrix of [`SmithWatermanMatcher`]'s scores.
+    pub(crate) scores: [Vec<Option<i64>>; 2],
+}
+
+impl Scratch {
+    /// Calls `f` with the current thread's buffers.
+    pub(crate) fn with<F, T>(f: F) -> T
+    where
+        F: FnOnce(&mut Self) -> T,
+    {
+        thread_local! {
+            static SCRATCH: RefCell<Scratch> = RefCell::default();
+        }
+
+        SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+            Ok(mut scratch) => f(&mut scratch),
+            // A matcher compares components within another comparison.
+            Err(_) => f(&mut Self::default()),
+        })
+    }
+
+    /// Stores the lowercase component and its characters.
+    pub(crate) fn set_component(&mut self, component: &str) {
+        self.lowercase.clear();
+        self.lowercase
+            .extend(component.chars().map(|c| c.to_ascii_lowercase()));
+        self.chars.clear();
+        self.chars.extend(self.lowercase.chars());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::abbr::Abbr;
+
+    #[test]
+    fn test_osa_distances() {
+        let mut rows = Default::default();
+        let mut distances = |a: &str, b: &str| {
+            let b = b.chars().collect::<Vec<_>>();
+
+            osa_distances(a, &b, &mut rows).to_vec()
+        };
+
+        assert_eq!(distances("abc", ""), [3]);
+        assert_eq!(distances("", "abc"), [0, 1, 2, 3]);
+        assert_eq!(distances("abc", "abc"), [3, 2, 1, 0]);
+        assert_eq!(distances("acb", "abc")[3], 1);
+        assert_eq!(distances("cofnig", "config")[6], 1);
+        assert_eq!(distances("kitten", "sitting")[7], 3);
+        // The rows of the previous comparisons are not reused.
+        assert_eq!(distances("ab", "ba"), [2, 1, 1]);
+    }
+
+    #[test]
+    fn test_default_matcher() {
+        let abbr = Abbr::new_sanitized("abc");
+
+        assert_variant!(
+            abbr.compare_with(&DefaultMatcher::default(), "abc"),
+            Some(Congruence::Complete)
+        );
+        assert_variant!(
+            abbr.compare_with(&DefaultMatcher::default(), "a_b_c"),
+            Some(Congruence::Boundary(2))
+        );
+        assert_variant!(
+            abbr.compare_with(&DefaultMatcher::default(), "xyz"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_exact_matcher() {
+        let abbr = Abbr::new_sanitized("Src");
+
+        assert_variant!(abbr.compare_with(&ExactMatcher, "sRC"), Some(()));
+        assert_variant!(abbr.compare_with(&ExactMatcher, "src_"), None);
+        assert_variant!(
+            abbr.compare_with(&ExactMatcher, "some_resource"),
+            None
+        );
+        assert_variant!(
+            Abbr::Wildcard.compare_with(&ExactMatcher, "anything"),
+            Some(())
+        );
+    }
+
+    #[test]
+    fn test_levenshtein_matcher() {
+        let matcher = LevenshteinMatcher::default();
+        let abbr = Abbr::new_sanitized("cofnig");
+
+        assert_variant!(
+            abbr.compare_with(&matcher, "config"),
+            Some(Distance {
+                edits: 1,
+                unmatched: 0
+            })
+        );
+        assert_variant!(
+            abbr.compare_with(&matcher, "Configuration"),
+            Some(Distance {
+                edits: 1,
+                unmatched: 7
+            })
+        );
+        assert_variant!(abbr.compare_with(&matcher, "documents"), None);
+
+        // At least one character must not be edited.
+        let abbr = Abbr::new_sanitized("x");
+        assert_variant!(abbr.compare_with(&matcher, "a"), None);
+        assert_variant!(
+            abbr.compare_with(&matcher, "xyz"),
+            Some(Distance {
+                edits: 0,
+                unmatched: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_smith_waterman_matcher() {
+        let abbr = Abbr::new_sanitized("fbs");
+        let score =
+            |component| abbr.compare_with(&SmithWatermanMatcher, component);
+
+        assert_variant!(score("xyz"), None);
+        assert!(score("foo-bar-service") < score("fabulous"));
+        assert!(score("fbs") < score("foo-bar-service"));
+
+        let abbr = Abbr::new_sanitized("doc");
+        let score =
+            |component| abbr.compare_with(&SmithWatermanMatcher, component);
+
+        assert!(score("docs") < score("dxoxc"));
+        assert!(score("my-docs") < score("mydocs"));
+    }
+
+    #[test]
+    fn test_order_paths() {
+        fn sort<'a, M>(
+            paths: &[&'a str],
+            abbr: &str,
+            matcher: &M,
+        ) -> Vec<&'a str>
+        where
+            M: Matcher,
+        {
+            let abbr = Abbr::new_sanitized(abbr);
+            let mut paths = paths.to_vec();
+            paths.sort_by_key(|path| abbr.compare_with(matcher, path).unwrap());
+
+            paths
+        }
+
+        let matcher = LevenshteinMatcher::default();
+        let paths = vec!["config", "cnofig", "conifg-old"];
+        assert_eq!(paths, sort(&paths, "config", &matcher));
+
+        let paths = vec!["src", "source", "some_resource"];
+        assert_eq!(paths, sort(&paths, "src", &SmithWatermanMatcher));
+    }
+}
@@ -1,147 +1,1706 @@
-//! The `query` subcommand.
+//! Queries of dirs matching abbreviations.
 
 use crate::{
-    abbr::{Abbr, Congruence},
+    abbr::{self, Abbr},
     error::Error,
+    listing::{DirReader, Entry, Lister, Metadata},
+    matcher::{DefaultMatcher, Matcher},
+    tiebreak::{self, Tiebreak},
 };
 
 use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
     convert::AsRef,
-    ffi::{OsStr, OsString},
-    fs::DirEntry,
-    mem,
+    env,
+    ffi::OsStr,
+    fmt,
+    fs,
+    panic,
     path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use alphanumeric_sort::compare_os_str;
+use glob::{MatchOptions, Pattern};
 
-/// A path matching an abbreviation.
+/// A single score in [`Congruences`](Congruences), linked to the score of the
+/// parent.
+#[derive(Debug)]
+struct Link<S> {
+    score: S,
+    parent: Option<Arc<Link<S>>>,
+}
+
+/// [`Congruence`](crate::abbr::Congruence)'s (or other
+/// [scores](Matcher::Score)) of a finding and its ancestors, with that of the
+/// closest ancestor first (so that they can be compared
+/// [lexicographically](std::cmp::Ord#lexicographical-comparison)).
 ///
-/// Stores [`Congruence`](Congruence)'s of its ancestors, with that of the
-/// closest ancestors first (so that it can be compared
-/// [lexicographically](std::cmp::Ord#lexicographical-comparison).
-struct Finding {
-    file_name: OsString,
+/// The scores of the ancestors are shared with all of their descendants
+/// instead of being copied into each of them.
+#[derive(Debug)]
+struct Congruences<S>(Option<Arc<Link<S>>>);
+
+impl<S> Congruences<S> {
+    /// Returns the congruences of the child with the given score.
+    fn push(&self, score: S) -> Self {
+        Self(Some(Arc::new(Link {
+            score,
+            parent: self.0.clone(),
+        })))
+    }
+
+    /// Returns the score of the finding itself.
+    fn first(&self) -> Option<&S> {
+        self.0.as_ref().map(|link| &link.score)
+    }
+
+    /// Returns the scores in the order of the components, the farthest
+    /// ancestor first.
+    fn to_vec(&self) -> Vec<S>
+    where
+        S: Clone,
+    {
+        let mut scores = vec![];
+        let mut link = self.0.as_ref();
+
+        while let Some(Link { score, parent }) = link.map(Arc::as_ref) {
+            scores.push(score.clone());
+            link = parent.as_ref();
+        }
+        scores.reverse();
+
+        scores
+    }
+}
+
+impl<S> Clone for Congruences<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<S> Ord for Congruences<S>
+where
+    S: Ord,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        let mut links = (self.0.as_ref(), other.0.as_ref());
+
+        loop {
+            match links {
+                // The rest of the scores is shared.
+                (Some(link_a), Some(link_b)) if Arc::ptr_eq(link_a, link_b) =>
+                    return Ordering::Equal,
+                (Some(link_a), Some(link_b)) =>
+                    match link_a.score.cmp(&link_b.score) {
+                        Ordering::Equal =>
+                            links =
+                                (link_a.parent.as_ref(), link_b.parent.as_ref()),
+                        ordering => return ordering,
+                    },
+                (None, None) => return Ordering::Equal,
+                (None, Some(_)) => return Ordering::Less,
+                (Some(_), None) => return Ordering::Greater,
+            }
+        }
+    }
+}
+
+impl<S> PartialOrd for Congruences<S>
+where
+    S: Ord,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S> PartialEq for Congruences<S>
+where
+    S: Ord,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<S> Eq for Congruences<S> where S: Ord {}
+
+/// A path matching an abbreviation.
+#[derive(Clone, Debug)]
+struct Finding<S> {
     path: PathBuf,
-    congruence: Vec<Congruence>,
+    congruences: Congruences<S>,
+}
+
+impl<S> Finding<S> {
+    /// Returns the name of the dir.
+    fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_default()
+    }
+}
+
+/// Checks if the dir is on the device. Symlinks are not resolved.
+fn is_on_device(lister: &dyn Lister, path: &Path, device: u64) -> bool {
+    lister.device(path) == Some(device)
+}
+
+/// Whether hidden dirs, with names starting with a dot, are searched.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Hidden {
+    /// Hidden dirs are searched like any other dirs.
+    #[default]
+    Include,
+
+    /// Hidden dirs are skipped. They may still be a part of the prefix of the
+    /// abbreviation, e.g. `~/.config/`.
+    Skip,
+}
+
+/// Glob's special characters.
+const GLOB_CHARS: [char; 3] = ['*', '?', '['];
+
+/// A dir excluded from the search along with its subtree.
+#[derive(Clone, Debug)]
+pub enum Exclusion {
+    /// The dir at the path. A relative path is relative to the current dir.
+    Path(PathBuf),
+
+    /// The dirs matching the glob pattern. A pattern without a `/` matches
+    /// dirs' names, e.g. `node_modules`. Other patterns match whole paths and
+    /// relative ones are relative to the current dir, e.g. `*/target`.
+    Pattern(Pattern),
+}
+
+impl Exclusion {
+    /// Parses the exclusion. It's a pattern if it contains any of glob's
+    /// special characters (`*`, `?` or `[`) or no `/` at all (so that it
+    /// matches dirs' names, e.g. `node_modules`) and a path otherwise, e.g.
+    /// `./node_modules`.
+    pub fn parse(exclusion: &OsStr) -> Result<Self, Error> {
+        match exclusion.to_str() {
+            Some(pattern)
+                if pattern.contains(&GLOB_CHARS[..])
+                    || !(pattern.contains('/')
+                        || pattern == "."
+                        || pattern == "..") =>
+                Pattern::new(pattern)
+                    .map(Self::Pattern)
+                    .map_err(|error| Error::InvalidPattern(error.to_string())),
+            _ => Ok(Self::Path(exclusion.into())),
+        }
+    }
+}
+
+impl From<PathBuf> for Exclusion {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&Path> for Exclusion {
+    fn from(path: &Path) -> Self {
+        Self::Path(path.into())
+    }
+}
+
+/// Parses the exclusion like [`Exclusion::parse`](Exclusion::parse) does. An
+/// invalid pattern is taken as a path.
+impl From<&str> for Exclusion {
+    fn from(exclusion: &str) -> Self {
+        Self::parse(OsStr::new(exclusion))
+            .unwrap_or_else(|_| Self::Path(exclusion.into()))
+    }
+}
+
+impl From<Pattern> for Exclusion {
+    fn from(pattern: Pattern) -> Self {
+        Self::Pattern(pattern)
+    }
+}
+
+/// Makes the path absolute and resolves `.` and `..` components lexically,
+/// like the shell's logical `$PWD` does.
+fn logical(path: &Path, current_dir: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in current_dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Makes the path absolute and resolves symlinks, like `getcwd` does. If the
+/// path doesn't exist, returns its [logical](logical) form.
+fn physical(path: &Path, current_dir: &Path) -> PathBuf {
+    fs::canonicalize(current_dir.join(path))
+        .unwrap_or_else(|_| logical(path, current_dir))
+}
+
+/// Normalizes paths so that they can be compared regardless of how they were
+/// written, e.g. `./x`, `/abs/x` and `/link/x` (with `/link` pointing to
+/// `/abs`) are equal. Every path has two forms, the [logical](logical) one,
+/// e.g. from `$OLDPWD`, and the [physical](physical) one, e.g. from the
+/// current dir.
+#[derive(Clone, Debug, Default)]
+struct Normalizer {
+    current_dir: PathBuf,
+
+    /// The start dir of the search, the prefix of every found path.
+    start_dir: PathBuf,
+
+    logical_start_dir: PathBuf,
+
+    physical_start_dir: PathBuf,
+}
+
+impl Normalizer {
+    /// Creates the normalizer of the paths found in the search starting in the
+    /// start dir, which may be relative to the current dir. If there's no
+    /// current dir, the process's one is used.
+    fn new(
+        start_dir: &Path,
+        current_dir: Option<&Path>,
+    ) -> Result<Self, Error> {
+        let current_dir = match current_dir {
+            Some(current_dir) => current_dir.into(),
+            None => env::current_dir()?,
+        };
+
+        Ok(Self {
+            logical_start_dir: logical(start_dir, &current_dir),
+            physical_start_dir: physical(start_dir, &current_dir),
+            start_dir: start_dir.into(),
+            current_dir,
+        })
+    }
+
+    /// Returns both forms of the path.
+    fn normalize(&self, path: &Path) -> [PathBuf; 2] {
+        [
+            logical(path, &self.current_dir),
+            physical(path, &self.current_dir),
+        ]
+    }
+
+    /// Returns both forms of the found path. The path's components below the
+    /// start dir are dirs' names, so they're appended to the start dir's forms
+    /// without reading the disk. Symlinks among them are not resolved.
+    fn normalize_found(&self, path: &Path) -> [PathBuf; 2] {
+        match path.strip_prefix(&self.start_dir) {
+            Ok(relative) => [
+                self.logical_start_dir.join(relative),
+                self.physical_start_dir.join(relative),
+            ],
+            Err(_) => self.normalize(path),
+        }
+    }
+
+    /// Returns the physical form of the found path without reading the disk,
+    /// like [`normalize_found`](Self::normalize_found) does. Paths outside the
+    /// start dir are returned as they are.
+    fn physical_found(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.start_dir) {
+            Ok(relative) => self.physical_start_dir.join(relative),
+            Err(_) => path.into(),
+        }
+    }
+}
+
+/// The avoided paths prepared for the search. They're compared with the found
+/// paths [normalized](Normalizer), so that e.g. `$OLDPWD` is avoided even if
+/// the search starts in a relative dir like `..`.
+#[derive(Clone, Debug, Default)]
+struct Avoided {
+    normalizer: Normalizer,
+
+    /// Both forms of every path.
+    paths: HashSet<PathBuf>,
+}
+
+impl Avoided {
+    /// Normalizes the paths for the search starting in the start dir. Relative
+    /// paths are relative to the current dir.
+    fn new<'a, I>(
+        start_dir: &Path,
+        current_dir: Option<&Path>,
+        paths: I,
+    ) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = &'a PathBuf>,
+    {
+        let mut paths = paths.into_iter().peekable();
+
+        if paths.peek().is_none() {
+            return Ok(Self::default());
+        }
+
+        let normalizer = Normalizer::new(start_dir, current_dir)?;
+        let paths = paths.flat_map(|path| normalizer.normalize(path)).collect();
+
+        Ok(Self { normalizer, paths })
+    }
+
+    /// Checks if the found path is avoided.
+    fn contains(&self, path: &Path) -> bool {
+        !self.paths.is_empty()
+            && self
+                .normalizer
+                .normalize_found(path)
+                .iter()
+                .any(|path| self.paths.contains(path))
+    }
+}
+
+/// The [`Exclusion`](Exclusion)'s prepared for the search. Excluded paths are
+/// compared with the found paths [normalized](Normalizer), so that e.g. `./x`
+/// and `/abs/x` are equal.
+#[derive(Debug, Default)]
+struct Excluded {
+    normalizer: Normalizer,
+
+    /// Both forms of every path.
+    paths: HashSet<PathBuf>,
+
+    /// The patterns matching whole paths, made absolute.
+    path_patterns: Vec<Pattern>,
+
+    /// The patterns matching dirs' names.
+    name_patterns: Vec<Pattern>,
+}
+
+impl Excluded {
+    /// Prepares the exclusions for the search starting in the start dir.
+    /// Relative paths and patterns are relative to the current dir.
+    fn new(
+        start_dir: &Path,
+        current_dir: Option<&Path>,
+        exclusions: &[Exclusion],
+    ) -> Result<Self, Error> {
+        let mut excluded = Self::default();
+        let (name_patterns, exclusions): (Vec<_>, Vec<_>) =
+            exclusions.iter().partition(|exclusion| {
+                matches!(
+                    exclusion,
+                    Exclusion::Pattern(pattern)
+                        if !pattern.as_str().contains('/')
+                )
+            });
+
+        for exclusion in name_patterns {
+            if let Exclusion::Pattern(pattern) = exclusion {
+                excluded.name_patterns.push(pattern.clone());
+            }
+        }
+
+        if exclusions.is_empty() {
+            return Ok(excluded);
+        }
+
+        excluded.normalizer = Normalizer::new(start_dir, current_dir)?;
+
+        for exclusion in exclusions {
+            match exclusion {
+                Exclusion::Path(path) =>
+                    excluded.paths.extend(excluded.normalizer.normalize(path)),
+                Exclusion::Pattern(pattern) => {
+                    let pattern = if Path::new(pattern.as_str()).is_absolute() {
+                        pattern.clone()
+                    } else {
+                        let current_dir = Pattern::escape(
+                            &excluded.normalizer.current_dir.to_string_lossy(),
+                        );
+                        let pattern =
+                            format!("{}/{}", current_dir, pattern.as_str());
+
+                        Pattern::new(&pattern).map_err(|error| {
+                            Error::InvalidPattern(error.to_string())
+                        })?
+                    };
+
+                    excluded.path_patterns.push(pattern);
+                }
+            }
+        }
+
+        Ok(excluded)
+    }
+
+    /// Checks if the dir found during the search is excluded.
+    fn excludes(&self, path: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::default()
+        };
+
+        if let Some(name) = path.file_name().and_then(OsStr::to_str) {
+            if self
+                .name_patterns
+                .iter()
+                .any(|pattern| pattern.matches_with(name, options))
+            {
+                return true;
+            }
+        }
+
+        if self.paths.is_empty() && self.path_patterns.is_empty() {
+            return false;
+        }
+
+        self.normalizer.normalize_found(path).iter().any(|path| {
+            self.paths.contains(path)
+                || self
+                    .path_patterns
+                    .iter()
+                    .any(|pattern| pattern.matches_path_with(path, options))
+        })
+    }
+}
+
+/// The dirs skipped during the search.
+#[derive(Clone, Debug)]
+struct Filter {
+    /// If provided, the dirs on other devices are skipped.
+    device: Option<u64>,
+
+    hidden: Hidden,
+
+    /// Whether symlinks are followed. If not, they are skipped.
+    follow_symlinks: bool,
+
+    /// The excluded dirs, skipped along with their subtrees.
+    excluded: Arc<Excluded>,
+
+    /// The normalizer of the found dirs, listed by their physical paths, so
+    /// that e.g. `../x` and `/link/x` are listed as `/abs/x`.
+    listed: Arc<Normalizer>,
+}
+
+impl Filter {
+    /// Creates the filter of the dirs under the start dir. If
+    /// `one_file_system` is `true`, the dirs on other devices than the start
+    /// dir, as reported by the [`Lister`](Lister), are skipped along with
+    /// symlinks, since their targets can't be checked without triggering
+    /// automounts.
+    fn new(
+        start_dir: &Path,
+        one_file_system: bool,
+        hidden: Hidden,
+        follow_symlinks: bool,
+        lister: &dyn Lister,
+    ) -> Self {
+        let device = if one_file_system {
+            // The trailing `.` resolves the start dir if it's a symlink.
+            lister.device(&start_dir.join("."))
+        } else {
+            None
+        };
+
+        Self {
+            device,
+            hidden,
+            follow_symlinks,
+            excluded: Arc::default(),
+            listed: Arc::default(),
+        }
+    }
+
+    /// Checks if the child is skipped without looking at its metadata. Files
+    /// are always skipped.
+    fn skips(&self, entry: &Entry) -> bool {
+        entry.is_file
+            || (self.hidden == Hidden::Skip && entry.name.starts_with('.'))
+            || ((!self.follow_symlinks || self.device.is_some())
+                && entry.is_symlink)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            device: None,
+            hidden: Hidden::Include,
+            follow_symlinks: true,
+            excluded: Arc::default(),
+            listed: Arc::default(),
+        }
+    }
+}
+
+/// A [`Lister`](Lister) remembering the listings of the dirs it has read, so
+/// that the searches from different depths don't read the same dirs again.
+/// Unlike the dirs read in the first pass of [`search`](search), the listings
+/// are not matched with the abbreviations yet, since each depth matches the
+/// dirs on a level with a different abbreviation. Files are left out.
+struct ListingMemo {
+    lister: Arc<dyn Lister>,
+    listings: Mutex<HashMap<PathBuf, Vec<Entry>>>,
+}
+
+impl ListingMemo {
+    fn new(lister: Arc<dyn Lister>) -> Self {
+        Self {
+            lister,
+            listings: Mutex::default(),
+        }
+    }
+}
+
+impl Lister for ListingMemo {
+    fn list(&self, dir: &Path) -> Vec<Entry> {
+        if let Some(entries) = self.listings.lock().unwrap().get(dir) {
+            return entries.clone();
+        }
+
+        // The lock isn't held while the dir is read.
+        let mut entries = self.lister.list(dir);
+        entries.retain(|entry| !entry.is_file);
+        self.listings
+            .lock()
+            .unwrap()
+            .insert(dir.into(), entries.clone());
+
+        entries
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.lister.is_dir(path)
+    }
+
+    fn metadata(&self, path: &Path) -> Metadata {
+        self.lister.metadata(path)
+    }
+
+    fn device(&self, path: &Path) -> Option<u64> {
+        self.lister.device(path)
+    }
 }
 
 /// Returns an interator over directory's children matching the abbreviation.
-fn get_matching_children<'a, P>(
-    path: &'a P,
+/// The children are skipped according to the [`Filter`](Filter).
+fn get_matching_children<'a, M>(
+    path: &'a Path,
     abbr: &'a Abbr,
-    parent_congruence: &'a [Congruence],
-) -> impl Iterator<Item = Finding> + 'a
+    matcher: &'a M,
+    parent_congruences: &'a Congruences<M::Score>,
+    filter: &'a Filter,
+    lister: &'a dyn Lister,
+) -> impl Iterator<Item = Finding<M::Score>> + 'a
 where
-    P: AsRef<Path>,
+    M: Matcher,
 {
-    let filter_map_entry = move |entry: DirEntry| {
-        let file_type = entry.file_type().ok()?;
+    let listed = filter.listed.physical_found(path);
 
-        if file_type.is_dir() || file_type.is_symlink() {
-            let file_name: String = entry.file_name().into_string().ok()?;
+    lister.list(&listed).into_iter().filter_map(move |entry| {
+        if filter.skips(&entry) {
+            return None;
+        }
 
-            if let Some(congruence) = abbr.compare(&file_name) {
-                let mut entry_congruence = parent_congruence.to_vec();
-                entry_congruence.insert(0, congruence);
+        let congruence = abbr.compare_with(matcher, &entry.name)?;
+        let child_path = path.join(&entry.name);
 
-                return Some(Finding {
-                    file_name: entry.file_name(),
-                    congruence: entry_congruence,
-                    path: entry.path(),
-                });
+        if let Some(device) = filter.device {
+            if !is_on_device(lister, &child_path, device) {
+                return None;
             }
         }
 
-        None
+        if filter.excluded.excludes(&child_path) {
+            return None;
+        }
+
+        Some(Finding {
+            path: child_path,
+            congruences: parent_congruences.push(congruence),
+        })
+    })
+}
+
+/// A callback receiving findings as soon as they're found.
+type Report<'a, S> = dyn Fn(&Finding<S>) + Sync + 'a;
+
+/// Reads the children of every parent matching the abbreviation, fanning the
+/// parents out across at most `threads` threads. The findings are returned in
+/// the order of their parents, regardless of the number of threads. Every
+/// finding is reported as soon as it's found. The dirs are skipped according to
+/// the [`Filter`](Filter). Once `cancelled` is set, no more dirs are read.
+#[allow(clippy::too_many_arguments)]
+fn expand_level<M>(
+    parents: &[Finding<M::Score>],
+    abbr: &Abbr,
+    matcher: &M,
+    threads: usize,
+    filter: &Filter,
+    lister: &dyn Lister,
+    report: &Report<'_, M::Score>,
+    cancelled: &AtomicBool,
+) -> Vec<Finding<M::Score>>
+where
+    M: Matcher + Sync,
+    M::Score: Send + Sync,
+{
+    let expand_chunk = |parents: &[Finding<M::Score>]| {
+        parents
+            .iter()
+            .take_while(|_| !cancelled.load(AtomicOrdering::Relaxed))
+            .flat_map(|parent| {
+                get_matching_children(
+                    &parent.path,
+                    abbr,
+                    matcher,
+                    &parent.congruences,
+                    filter,
+                    lister,
+                )
+            })
+            .inspect(|finding| report(finding))
+            .collect::<Vec<_>>()
     };
 
-    path.as_ref()
-        .read_dir()
-        .ok()
-        .map(|reader| {
-            reader
-                .filter_map(|entry| entry.ok())
-                .filter_map(filter_map_entry)
+    if threads <= 1 || parents.len() <= 1 {
+        return expand_chunk(parents);
+    }
+
+    let chunk_size = parents.len().div_ceil(threads);
+
+    thread::scope(|scope| {
+        let handles = parents
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || expand_chunk(chunk)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| match handle.join() {
+                Ok(findings) => findings,
+                Err(panic) => panic::resume_unwind(panic),
+            })
+            .collect()
+    })
+}
+
+/// Removes [fallback](Matcher::is_fallback) findings from the level unless
+/// there are no other findings.
+fn drop_fallbacks<M>(level: &mut Vec<Finding<M::Score>>, matcher: &M)
+where
+    M: Matcher,
+{
+    let is_fallback = |finding: &Finding<M::Score>| {
+        finding
+            .congruences
+            .first()
+            .is_some_and(|score| matcher.is_fallback(score))
+    };
+
+    if !level.iter().all(is_fallback) {
+        level.retain(|finding| !is_fallback(finding));
+    }
+}
+
+/// Selects the finding with the best congruence. The ties are resolved with
+/// the [`Tiebreak`](Tiebreak)'s.
+fn select_best<S, I>(
+    findings: I,
+    tiebreaks: &[Tiebreak],
+    lister: &dyn Lister,
+) -> Option<Finding<S>>
+where
+    S: Ord,
+    I: Iterator<Item = Finding<S>>,
+{
+    let mut best: Vec<Finding<S>> = vec![];
+
+    for finding in findings {
+        match best
+            .first()
+            .map(|best| finding.congruences.cmp(&best.congruences))
+        {
+            None | Some(Ordering::Equal) => best.push(finding),
+            Some(Ordering::Less) => best = vec![finding],
+            Some(Ordering::Greater) => {}
+        }
+    }
+
+    if best.len() <= 1 {
+        return best.pop();
+    }
+
+    best.into_iter()
+        .map(|finding| {
+            let keys = tiebreak::keys(
+                tiebreaks,
+                &finding.path,
+                finding.file_name(),
+                lister,
+            );
+
+            (keys, finding)
         })
+        .min_by(|(keys_a, _), (keys_b, _)| {
+            tiebreak::compare_keys(keys_a, keys_b)
+        })
+        .map(|(_, finding)| finding)
+}
+
+/// Orders the findings with equal congruences with the
+/// [`Tiebreak`](Tiebreak)'s.
+fn sort_ties<S>(
+    findings: Vec<Finding<S>>,
+    tiebreaks: &[Tiebreak],
+    lister: &dyn Lister,
+) -> Vec<Finding<S>> {
+    let mut findings = findings
         .into_iter()
-        .flatten()
+        .map(|finding| {
+            let keys = tiebreak::keys(
+                tiebreaks,
+                &finding.path,
+                finding.file_name(),
+                lister,
+            );
+
+            (keys, finding)
+        })
+        .collect::<Vec<_>>();
+    findings.sort_by(|(keys_a, _), (keys_b, _)| {
+        tiebreak::compare_keys(keys_a, keys_b)
+    });
+
+    findings.into_iter().map(|(_, finding)| finding).collect()
+}
+
+/// Limits of the search, keeping it interactive in large trees.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Limits {
+    /// The maximal number of findings retained on each level of the search.
+    /// The findings with the best congruences are retained.
+    pub beam_width: Option<usize>,
+
+    /// The maximal number of dirs read during the search.
+    pub max_dirs: Option<usize>,
+
+    /// Whether the dirs on other devices than the start dir, as reported by
+    /// [`Lister::device`](Lister::device), are skipped along with symlinks. It
+    /// prevents triggering automounts. Has no effect on platforms other than
+    /// Unix.
+    pub one_file_system: bool,
+}
+
+/// A path found by [`Query::find`](Query::find).
+#[derive(Debug)]
+pub struct Found {
+    /// The found path.
+    pub path: PathBuf,
+
+    /// Whether the search was truncated because of the [`Limits`](Limits). If
+    /// it was, there may be a better matching path.
+    pub truncated: bool,
+}
+
+/// Keeps only the `n` best findings on the level if there are more. The
+/// findings are ranked by their congruences and then by their paths, so the
+/// retained ones don't depend on the order in which the dirs were listed.
+/// Returns whether any finding was removed.
+fn keep_best<S>(level: &mut Vec<Finding<S>>, n: Option<usize>) -> bool
+where
+    S: Ord,
+{
+    match n {
+        Some(n) if level.len() > n => {
+            level.sort_by(|finding_a, finding_b| {
+                finding_a.congruences.cmp(&finding_b.congruences).then_with(
+                    || {
+                        compare_os_str(
+                            finding_a.path.as_os_str(),
+                            finding_b.path.as_os_str(),
+                        )
+                    },
+                )
+            });
+            level.truncate(n);
+
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The result of [`search`](search).
+struct Outcome<S> {
+    /// The findings on the last level.
+    findings: Vec<Finding<S>>,
+
+    /// Whether the search was truncated.
+    truncated: bool,
+
+    /// Whether the search timed out.
+    timed_out: bool,
+}
+
+/// The children matching the abbreviation of every dir read on each level of
+/// the first pass of [`search`](search).
+type ReadDirs<S> = Vec<HashMap<PathBuf, Vec<Finding<S>>>>;
+
+/// The parameters shared by both passes of [`search`](search).
+struct Search<'a, M>
+where
+    M: Matcher,
+{
+    abbrs: &'a [Abbr],
+    matcher: &'a M,
+    avoided: &'a Avoided,
+    limits: Limits,
+    threads: usize,
+    filter: &'a Filter,
+    lister: &'a dyn Lister,
+    report: &'a Report<'a, M::Score>,
+    cancelled: &'a AtomicBool,
 }
 
-/// The `query` subcommand.
+/// Searches for the dirs matching the abbreviations, first following only the
+/// dirs with the [best score](Matcher::best_score) and then, if no such path
+/// other than the avoided ones is found, following every matching dir. The
+/// findings on the last level are reported as soon as they're found.
 ///
-/// It takes two args — `--abbr` and `--exclude` (optionally). The value of
-/// `--abbr` gets split into a prefix containing components like `c:/`, `/`,
-/// `~/`, and dots, and [`Abbr`](Abbr)'s. If there is more than one dir matching
-/// the query, the value of `--exclude` is excluded from the search.
-pub fn query<P>(arg: &P, excluded: Option<PathBuf>) -> Result<PathBuf, Error>
+/// Since congruences are compared lexicographically, a path with the best
+/// score on every level comes before every other path, so the rest of the
+/// tree doesn't have to be searched. It makes literal queries nearly as cheap
+/// as `cd`. The dirs read in the first pass are not read again in the second.
+/// If `exhaustive` is `true`, the first pass is skipped. The dirs are listed by
+/// the [`Lister`](Lister) and skipped according to the [`Filter`](Filter).
+/// Once `cancelled` is set, no more dirs are read and the outcome is
+/// incomplete.
+#[allow(clippy::too_many_arguments)]
+fn search<M>(
+    start_dir: PathBuf,
+    abbrs: &[Abbr],
+    matcher: &M,
+    avoided: &Avoided,
+    limits: Limits,
+    threads: usize,
+    filter: &Filter,
+    lister: &dyn Lister,
+    report: &Report<'_, M::Score>,
+    cancelled: &AtomicBool,
+    exhaustive: bool,
+) -> Outcome<M::Score>
 where
-    P: AsRef<Path>,
+    M: Matcher + Sync,
+    M::Score: Send + Sync,
 {
-    // If the arg is a real path and not an abbreviation, return it. It
-    // prevents potential unexpected behavior due to abbreviation expansion.
-    // For example, `kn` doesn't allow for any component other than `Normal` in
-    // the abbreviation but the arg itself may be a valid path. `kn` should only
-    // behave differently from `cd` in situations where `cd` would fail.
-    if arg.as_ref().is_dir() {
-        return Ok(arg.as_ref().into());
-    }
-
-    let (prefix, abbrs) = parse_arg(&arg)?;
-    let start_dir = match prefix {
-        Some(start_dir) => start_dir,
-        None => std::env::current_dir()?,
+    let search = Search {
+        abbrs,
+        matcher,
+        avoided,
+        limits,
+        threads,
+        filter,
+        lister,
+        report,
+        cancelled,
     };
+    let mut read_dirs = abbrs.iter().map(|_| HashMap::new()).collect();
+    let mut n_dirs_read = 0;
 
-    match abbrs.as_slice() {
-        [] => Ok(start_dir),
-        [first_abbr, abbrs @ ..] => {
-            let mut current_level =
-                get_matching_children(&start_dir, first_abbr, &[])
-                    .collect::<Vec<_>>();
-            let mut next_level = vec![];
+    if exhaustive {
+        return search.levels(
+            &start_dir,
+            false,
+            &mut read_dirs,
+            &mut n_dirs_read,
+        );
+    }
 
-            for abbr in abbrs {
-                let children = current_level
-                    .iter()
-                    .map(|parent| {
-                        get_matching_children(
-                            &parent.path,
-                            abbr,
-                            &parent.congruence,
-                        )
-                    })
-                    .flatten();
+    let outcome =
+        search.levels(&start_dir, true, &mut read_dirs, &mut n_dirs_read);
+
+    if outcome
+        .findings
+        .iter()
+        .any(|finding| !avoided.contains(&finding.path))
+    {
+        return outcome;
+    }
+
+    search.levels(&start_dir, false, &mut read_dirs, &mut n_dirs_read)
+}
+
+impl<M> Search<'_, M>
+where
+    M: Matcher + Sync,
+    M::Score: Send + Sync,
+{
+    /// Searches for the dirs matching the abbreviations level by level,
+    /// starting from `start_dir`. If `only_best` is `true`, only the dirs with
+    /// the [best score](Matcher::best_score) are retained on each level and,
+    /// unless a path other than the avoided ones is found, the dirs read are
+    /// stored in `read_dirs`. Otherwise the dirs already in `read_dirs` are
+    /// not read again.
+    fn levels(
+        &self,
+        start_dir: &Path,
+        only_best: bool,
+        read_dirs: &mut ReadDirs<M::Score>,
+        n_dirs_read: &mut usize,
+    ) -> Outcome<M::Score> {
+        let best_score = self.matcher.best_score();
+        let mut current_level = vec![Finding {
+            path: start_dir.into(),
+            congruences: Congruences(None),
+        }];
+        let mut truncated = false;
+
+        for (i, (abbr, read_level)) in
+            self.abbrs.iter().zip(read_dirs.iter_mut()).enumerate()
+        {
+            let (read_parents, mut parents): (Vec<_>, Vec<_>) = current_level
+                .into_iter()
+                .partition(|parent| read_level.contains_key(&parent.path));
+            let mut next_level = read_parents
+                .iter()
+                .filter_map(|parent| read_level.remove(&parent.path))
+                .flatten()
+                .collect::<Vec<_>>();
+
+            if let Some(max_dirs) = self.limits.max_dirs {
+                let n_dirs_left = max_dirs.saturating_sub(*n_dirs_read);
+
+                // The best matching dirs are read first.
+                truncated |= keep_best(&mut parents, Some(n_dirs_left));
+            }
+
+            *n_dirs_read += parents.len();
+
+            let is_last = i + 1 == self.abbrs.len();
+            let report: &Report<'_, M::Score> =
+                if is_last { self.report } else { &|_| {} };
+            let children = expand_level(
+                &parents,
+                abbr,
+                self.matcher,
+                self.threads,
+                self.filter,
+                self.lister,
+                report,
+                self.cancelled,
+            );
+
+            if only_best {
+                let (best, rest): (Vec<_>, Vec<_>) =
+                    children.into_iter().partition(|finding| {
+                        finding.congruences.first() == Some(&best_score)
+                    });
 
-                next_level.clear();
+                // The second pass won't be needed, there's no need to store
+                // the dirs read.
+                let is_found = is_last
+                    && best
+                        .iter()
+                        .any(|finding| !self.avoided.contains(&finding.path));
+
+                if !is_found {
+                    for parent in parents {
+                        read_level.insert(parent.path, vec![]);
+                    }
+
+                    for child in best.iter().cloned().chain(rest) {
+                        if let Some(siblings) = child
+                            .path
+                            .parent()
+                            .and_then(|parent| read_level.get_mut(parent))
+                        {
+                            siblings.push(child);
+                        }
+                    }
+                }
+
+                next_level.extend(best);
+            } else {
                 next_level.extend(children);
+            }
+
+            drop_fallbacks(&mut next_level, self.matcher);
+            truncated |= keep_best(&mut next_level, self.limits.beam_width);
+
+            current_level = next_level;
+        }
+
+        Outcome {
+            findings: current_level,
+            truncated,
+            timed_out: false,
+        }
+    }
+}
+
+/// A message sent by the search thread in
+/// [`search_with_timeout`](search_with_timeout).
+enum Message<S> {
+    /// A finding on the last level.
+    Found(Finding<S>),
+
+    /// The search is complete.
+    Done(Outcome<S>),
+}
+
+/// Runs [`search`](search) on a separate thread. If it doesn't complete before
+/// the timeout, returns the findings on the last level found so far and
+/// cancels the search, so the thread stops reading dirs. A `read_dir` blocked
+/// on a hung mount can't be interrupted though, so the thread ends only once
+/// the call returns.
+#[allow(clippy::too_many_arguments)]
+fn search_with_timeout<M>(
+    start_dir: PathBuf,
+    abbrs: Vec<Abbr>,
+    matcher: M,
+    avoided: Avoided,
+    limits: Limits,
+    threads: usize,
+    filter: Filter,
+    lister: Arc<dyn Lister>,
+    timeout: Duration,
+    exhaustive: bool,
+) -> Outcome<M::Score>
+where
+    M: Matcher + Clone + Send + Sync + 'static,
+    M::Score: Send + Sync + 'static,
+{
+    let deadline = Instant::now() + timeout;
+    let (sender, receiver) = mpsc::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    let search_matcher = matcher.clone();
+    let search_cancelled = Arc::clone(&cancelled);
+
+    thread::spawn(move || {
+        let report = |finding: &Finding<M::Score>| {
+            let _ = sender.send(Message::Found(finding.clone()));
+        };
+        let outcome = search(
+            start_dir,
+            &abbrs,
+            &search_matcher,
+            &avoided,
+            limits,
+            threads,
+            &filter,
+            lister.as_ref(),
+            &report,
+            &search_cancelled,
+            exhaustive,
+        );
+        let _ = sender.send(Message::Done(outcome));
+    });
+
+    let mut findings = vec![];
+
+    loop {
+        let time_left = deadline.saturating_duration_since(Instant::now());
+
+        match receiver.recv_timeout(time_left) {
+            Ok(Message::Found(finding)) => findings.push(finding),
+            Ok(Message::Done(outcome)) => return outcome,
+            Err(RecvTimeoutError::Timeout) => break,
+            // The search thread has panicked.
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    cancelled.store(true, AtomicOrdering::Relaxed);
+    drop_fallbacks(&mut findings, &matcher);
 
-                mem::swap(&mut next_level, &mut current_level);
+    Outcome {
+        findings,
+        truncated: true,
+        timed_out: true,
+    }
+}
+
+/// Options of a [`Query`](Query), set with builder methods.
+///
+/// ```no_run
+/// use kn::{Hidden, Query, QueryOptions};
+///
+/// let options = QueryOptions::new()
+///     .start_dir("/home/user")
+///     .hidden(Hidden::Skip)
+///     .depth(3)
+///     .limit(10);
+///
+/// for found in Query::with_options("kn/src", options).matches()? {
+///     println!("{}", found.path.display());
+/// }
+/// # Ok::<(), kn::Error>(())
+/// ```
+#[derive(Clone)]
+pub struct QueryOptions<M = DefaultMatcher> {
+    start_dir: Option<PathBuf>,
+    current_dir: Option<PathBuf>,
+    excluded: Vec<Exclusion>,
+    avoided: Vec<PathBuf>,
+    depth: usize,
+    hidden: Hidden,
+    matcher: M,
+    limit: Option<usize>,
+    follow_symlinks: bool,
+    tiebreaks: Vec<Tiebreak>,
+    limits: Limits,
+    threads: usize,
+    timeout: Option<Duration>,
+    lister: Arc<dyn Lister>,
+}
+
+impl QueryOptions {
+    /// Creates the default options. The search starts in the current dir and
+    /// the first abbreviation matches only its children. Hidden dirs and
+    /// symlinks are searched. The dirs are read from the disk by a single
+    /// thread, with no limits and no timeout.
+    pub fn new() -> Self {
+        Self {
+            start_dir: None,
+            current_dir: None,
+            excluded: vec![],
+            avoided: vec![],
+            depth: 1,
+            hidden: Hidden::Include,
+            matcher: DefaultMatcher::default(),
+            limit: None,
+            follow_symlinks: true,
+            tiebreaks: vec![],
+            limits: Limits::default(),
+            threads: 1,
+            timeout: None,
+            lister: Arc::new(DirReader),
+        }
+    }
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> fmt::Debug for QueryOptions<M>
+where
+    M: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryOptions")
+            .field("start_dir", &self.start_dir)
+            .field("current_dir", &self.current_dir)
+            .field("excluded", &self.excluded)
+            .field("avoided", &self.avoided)
+            .field("depth", &self.depth)
+            .field("hidden", &self.hidden)
+            .field("matcher", &self.matcher)
+            .field("limit", &self.limit)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("tiebreaks", &self.tiebreaks)
+            .field("limits", &self.limits)
+            .field("threads", &self.threads)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<M> QueryOptions<M> {
+    /// Sets the dir in which the search starts, unless the abbreviation starts
+    /// with an absolute path. A relative prefix of the abbreviation, e.g.
+    /// `../`, is relative to it.
+    pub fn start_dir<P>(mut self, start_dir: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.start_dir = Some(start_dir.into());
+
+        self
+    }
+
+    /// Sets the dir against which relative paths are resolved instead of the
+    /// process's current dir: the start dir, the abbreviation's prefix and the
+    /// excluded and avoided paths. Without a start dir, the search starts in
+    /// it. Useful when searching on behalf of another process.
+    pub fn current_dir<P>(mut self, current_dir: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.current_dir = Some(current_dir.into());
+
+        self
+    }
+
+    /// Excludes the dir (or the dirs matching the pattern) along with its
+    /// subtree from the search, on every level. May be called many times.
+    pub fn exclude<E>(mut self, exclusion: E) -> Self
+    where
+        E: Into<Exclusion>,
+    {
+        self.excluded.push(exclusion.into());
+
+        self
+    }
+
+    /// Avoids the path: it's returned only if no other path matches, e.g. to
+    /// skip the previous location. Unlike excluded dirs, its subtree is
+    /// searched. May be called many times.
+    pub fn avoid<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.avoided.push(path.into());
+
+        self
+    }
+
+    /// Sets the number of levels below the start dir at which the first
+    /// abbreviation may match, 1 by default. The skipped levels are matched
+    /// like wildcards, so out of equally matching paths the shallowest one
+    /// comes first.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+
+        self
+    }
+
+    /// Sets whether hidden dirs are searched.
+    pub fn hidden(mut self, hidden: Hidden) -> Self {
+        self.hidden = hidden;
+
+        self
+    }
+
+    /// Sets the [`Matcher`](Matcher) comparing the abbreviations with dir
+    /// names.
+    pub fn matcher<N>(self, matcher: N) -> QueryOptions<N> {
+        QueryOptions {
+            start_dir: self.start_dir,
+            current_dir: self.current_dir,
+            excluded: self.excluded,
+            avoided: self.avoided,
+            depth: self.depth,
+            hidden: self.hidden,
+            matcher,
+            limit: self.limit,
+            follow_symlinks: self.follow_symlinks,
+            tiebreaks: self.tiebreaks,
+            limits: self.limits,
+            threads: self.threads,
+            timeout: self.timeout,
+            lister: self.lister,
+        }
+    }
+
+    /// Sets the maximal number of paths returned by
+    /// [`Query::matches`](Query::matches).
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+
+        self
+    }
+
+    /// Sets whether symlinks to dirs are followed. If not, they are skipped.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+
+        self
+    }
+
+    /// Sets the [`Tiebreak`](Tiebreak)'s ordering paths with equal scores.
+    pub fn tiebreaks(mut self, tiebreaks: Vec<Tiebreak>) -> Self {
+        self.tiebreaks = tiebreaks;
+
+        self
+    }
+
+    /// Sets the [`Limits`](Limits) truncating the search.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+
+        self
+    }
+
+    /// Sets the maximal number of threads reading the dirs on each level.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+
+        self
+    }
+
+    /// Sets the time after which the search gives up and returns the paths
+    /// found so far. The search runs on a separate thread which stops reading
+    /// dirs after the timeout. If it's blocked reading a dir, e.g. on a hung
+    /// mount, it ends once the read returns.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+
+        self
+    }
+
+    /// Sets the [`Lister`](Lister) listing the dirs.
+    pub fn lister(mut self, lister: Arc<dyn Lister>) -> Self {
+        self.lister = lister;
+
+        self
+    }
+}
+
+/// A query of the dirs matching an abbreviation.
+///
+/// The abbreviation gets split into a prefix containing components like `c:/`,
+/// `/`, `~/`, and dots, and [`Abbr`](Abbr)'s. The search starts in the prefix
+/// or, if there's none, in the start dir. The dir names are compared with the
+/// abbreviations using the [`Matcher`](Matcher), by default the
+/// [`DefaultMatcher`](DefaultMatcher). The query is configured with
+/// [`QueryOptions`](QueryOptions).
+///
+/// ```no_run
+/// use kn::Query;
+///
+/// let found = Query::new("pr/kn").find()?;
+/// println!("{}", found.path.display());
+///
+/// for found in Query::new("pr/kn").matches()? {
+///     println!("{} {:?}", found.path.display(), found.scores);
+/// }
+/// # Ok::<(), kn::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct Query<M = DefaultMatcher> {
+    args: Vec<PathBuf>,
+    options: QueryOptions<M>,
+}
+
+impl Query {
+    /// Creates the query of the abbreviation with the default
+    /// [`QueryOptions`](QueryOptions).
+    pub fn new<P>(arg: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_options(arg, QueryOptions::new())
+    }
+}
+
+impl<M> Query<M>
+where
+    M: Matcher + Clone + Send + Sync + 'static,
+    M::Score: Send + Sync + 'static,
+{
+    /// Creates the query of the abbreviation with the options.
+    pub fn with_options<P>(arg: P, options: QueryOptions<M>) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_args([arg], options)
+    }
+
+    /// Creates the query of the abbreviation passed as many args with the
+    /// options. Each arg holds one or more consecutive components, so
+    /// `["pr", "kn"]` is the same as `"pr/kn"`.
+    pub fn with_args<I, P>(args: I, options: QueryOptions<M>) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        Self {
+            args: args.into_iter().map(|arg| arg.as_ref().into()).collect(),
+            options,
+        }
+    }
+
+    /// Returns the best matching path. Paths with equal scores are ordered
+    /// with the [`Tiebreak`](Tiebreak)'s.
+    pub fn find(&self) -> Result<Found, Error> {
+        let Outcome {
+            findings,
+            truncated,
+            timed_out,
+        } = self.search(false)?;
+
+        let found_path = select_best(
+            findings.into_iter(),
+            &self.options.tiebreaks,
+            &*self.options.lister,
+        )
+        .map(|Finding { path, .. }| path);
+
+        match found_path {
+            Some(path) => Ok(Found { path, truncated }),
+            None if timed_out => Err(Error::Timeout),
+            None if truncated => Err(Error::SearchTruncated),
+            None => Err(Error::PathNotFound),
+        }
+    }
+
+    /// Returns every matching path (or as many as the
+    /// [limit](QueryOptions::limit)), the best first. Unlike
+    /// [`find`](Self::find), it searches the whole tree even if the best path
+    /// is found early.
+    pub fn matches(&self) -> Result<Matches<M::Score>, Error>
+    where
+        M::Score: Clone,
+    {
+        let Outcome {
+            findings,
+            truncated,
+            ..
+        } = self.search(true)?;
+
+        let mut findings = findings;
+        findings.sort_by(|finding_a, finding_b| {
+            finding_a.congruences.cmp(&finding_b.congruences)
+        });
+
+        // The keys are computed only for the findings with equal congruences
+        // which are returned.
+        let limit = self.options.limit.unwrap_or(findings.len());
+        let mut findings = findings.into_iter().peekable();
+        let mut sorted = vec![];
+
+        while sorted.len() < limit {
+            let mut ties = match findings.next() {
+                Some(first) => vec![first],
+                None => break,
+            };
+
+            while let Some(tie) = findings.next_if(|finding| {
+                finding.congruences.cmp(&ties[0].congruences) == Ordering::Equal
+            }) {
+                ties.push(tie);
             }
 
-            let cmp_findings = |finding_a: &Finding, finding_b: &Finding| {
-                finding_a.congruence.cmp(&finding_b.congruence).then(
-                    compare_os_str(&finding_a.file_name, &finding_b.file_name),
-                )
+            if ties.len() > 1 {
+                ties = sort_ties(
+                    ties,
+                    &self.options.tiebreaks,
+                    &*self.options.lister,
+                );
+            }
+
+            sorted.extend(ties);
+        }
+
+        sorted.truncate(limit);
+
+        let matches = sorted
+            .into_iter()
+            .map(|finding| Match {
+                scores: finding.congruences.to_vec(),
+                path: finding.path,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Matches {
+            matches: matches.into_iter(),
+            truncated,
+        })
+    }
+
+    /// Searches for the paths matching the abbreviation. If `exhaustive` is
+    /// `true`, the whole tree is searched even if paths with the best scores
+    /// are found early.
+    fn search(&self, exhaustive: bool) -> Result<Outcome<M::Score>, Error> {
+        let options = &self.options;
+        let single = |path| Outcome {
+            findings: vec![Finding {
+                path,
+                congruences: Congruences(None),
+            }],
+            truncated: false,
+            timed_out: false,
+        };
+        let resolve = |path: PathBuf| match &options.current_dir {
+            Some(current_dir) => current_dir.join(path),
+            None => path,
+        };
+        let arg = join_args(&self.args)
+            .map(|arg| match &options.start_dir {
+                Some(start_dir) => start_dir.join(arg),
+                None => arg,
+            })
+            .map(resolve);
+
+        // If the arg is a real path and not an abbreviation, return it. It
+        // prevents potential unexpected behavior due to abbreviation
+        // expansion. For example, `kn` doesn't allow for any component other
+        // than `Normal` in the abbreviation but the arg itself may be a valid
+        // path. `kn` should only behave differently from `cd` in situations
+        // where `cd` would fail.
+        if let Some(arg) = arg.filter(|arg| options.lister.is_dir(arg)) {
+            return Ok(single(arg));
+        }
+
+        let (prefix, abbrs) = parse_args(&self.args)?;
+        let start_dir = match (&options.start_dir, prefix) {
+            (Some(start_dir), Some(prefix)) => resolve(start_dir.join(prefix)),
+            (Some(start_dir), None) => resolve(start_dir.clone()),
+            (None, Some(prefix)) => resolve(prefix),
+            (None, None) => match &options.current_dir {
+                Some(current_dir) => current_dir.clone(),
+                None => env::current_dir()?,
+            },
+        };
+
+        if abbrs.is_empty() {
+            return Ok(single(start_dir));
+        }
+
+        let filter = Filter {
+            excluded: Arc::new(Excluded::new(
+                &start_dir,
+                options.current_dir.as_deref(),
+                &options.excluded,
+            )?),
+            listed: Arc::new(Normalizer::new(
+                &start_dir,
+                options.current_dir.as_deref(),
+            )?),
+            ..Filter::new(
+                &start_dir,
+                options.limits.one_file_system,
+                options.hidden,
+                options.follow_symlinks,
+                options.lister.as_ref(),
+            )
+        };
+        let avoided = Avoided::new(
+            &start_dir,
+            options.current_dir.as_deref(),
+            &options.avoided,
+        )?;
+        let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+        let mut outcome = Outcome {
+            findings: vec![],
+            truncated: false,
+            timed_out: false,
+        };
+
+        let lister: Arc<dyn Lister> = if options.depth > 1 {
+            Arc::new(ListingMemo::new(Arc::clone(&options.lister)))
+        } else {
+            Arc::clone(&options.lister)
+        };
+
+        // The levels above the first abbreviation are skipped with wildcards.
+        for n_skipped in 0..options.depth {
+            let abbrs = std::iter::repeat_n(Abbr::Wildcard, n_skipped)
+                .chain(abbrs.iter().cloned())
+                .collect::<Vec<_>>();
+            let Outcome {
+                findings,
+                truncated,
+                timed_out,
+            } = match deadline {
+                Some(deadline) => search_with_timeout(
+                    start_dir.clone(),
+                    abbrs,
+                    options.matcher.clone(),
+                    avoided.clone(),
+                    options.limits,
+                    options.threads,
+                    filter.clone(),
+                    Arc::clone(&lister),
+                    deadline.saturating_duration_since(Instant::now()),
+                    exhaustive,
+                ),
+                None => search(
+                    start_dir.clone(),
+                    &abbrs,
+                    &options.matcher,
+                    &avoided,
+                    options.limits,
+                    options.threads,
+                    &filter,
+                    lister.as_ref(),
+                    &|_| {},
+                    &AtomicBool::new(false),
+                    exhaustive,
+                ),
             };
 
-            let found_path = match excluded {
-                Some(excluded) if current_level.len() > 1 => current_level
-                    .into_iter()
-                    .filter(|finding| finding.path != excluded)
-                    .min_by(cmp_findings)
-                    .map(|Finding { path, .. }| path),
-                _ => current_level
-                    .into_iter()
-                    .min_by(cmp_findings)
-                    .map(|Finding { path, .. }| path),
-            };
+            outcome.findings.extend(findings);
+            outcome.truncated |= truncated;
+            outcome.timed_out |= timed_out;
+
+            if timed_out {
+                break;
+            }
+        }
+
+        // Avoided paths are returned only if there are no other paths.
+        if outcome
+            .findings
+            .iter()
+            .any(|finding| !avoided.contains(&finding.path))
+        {
+            outcome
+                .findings
+                .retain(|finding| !avoided.contains(&finding.path));
+        }
+
+        Ok(outcome)
+    }
+}
+
+/// A path found by [`Query::matches`](Query::matches).
+#[derive(Clone, Debug)]
+pub struct Match<S> {
+    /// The found path.
+    pub path: PathBuf,
+
+    /// The [`Congruence`](crate::abbr::Congruence)'s (or other
+    /// [scores](Matcher::Score)) of the path's components below the start
+    /// dir, in the order of the components.
+    pub scores: Vec<S>,
+}
+
+/// An iterator over the paths found by [`Query::matches`](Query::matches),
+/// the best first.
+#[derive(Debug)]
+pub struct Matches<S> {
+    matches: std::vec::IntoIter<Match<S>>,
+    truncated: bool,
+}
+
+impl<S> Matches<S> {
+    /// Whether the search was truncated because of the [`Limits`](Limits) or
+    /// the timeout. If it was, some paths may be missing.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl<S> Iterator for Matches<S> {
+    type Item = Match<S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.matches.next()
+    }
 
-            found_path.ok_or(Error::PathNotFound)
-        }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.matches.size_hint()
     }
 }
 
+impl<S> ExactSizeIterator for Matches<S> {}
+
 /// Checks if the component contains only dots and returns the equivalent number
 /// of [`ParentDir`](Component::ParentDir) components if it does.
 ///
@@ -169,16 +1728,14 @@ fn parse_dots(component: &str) -> Option<usize> {
 /// **unless** it only contains dots. In this case, it converts it to as many
 /// [`ParentDir`](Component::ParentDir)'s as there are dots in this component,
 /// less one. For example, `...` is converted to `../..`, `....` to `../../..`
-/// etc.
-fn extract_prefix<'a, P>(
-    arg: &'a P,
-) -> Result<(Option<PathBuf>, impl Iterator<Item = Component<'a>> + 'a), Error>
+/// etc. Returns the prefix and the rest of the path.
+fn extract_prefix<P>(arg: &P) -> Result<(Option<PathBuf>, &Path), Error>
 where
-    P: AsRef<Path> + ?Sized + 'a,
+    P: AsRef<Path> + ?Sized,
 {
     use Component::*;
 
-    let mut components = arg.as_ref().components().peekable();
+    let mut components = arg.as_ref().components();
     let mut prefix: Option<PathBuf> = None;
     let mut push_to_prefix = |component: Component| match &mut prefix {
         None => prefix = Some(PathBuf::from(&component)),
@@ -192,74 +1749,138 @@ where
             .map(|component| parse_dots(&component))
     };
 
-    while let Some(component) = components.peek() {
-        match component {
-            Prefix(_) | RootDir | CurDir | ParentDir =>
-                push_to_prefix(*component),
-            Normal(component_os) => {
+    loop {
+        let mut rest = components.clone();
+
+        match rest.next() {
+            Some(component @ (Prefix(_) | RootDir | CurDir | ParentDir)) =>
+                push_to_prefix(component),
+            Some(Normal(component_os)) => {
                 if let Some(n_dots) = parse_dots_os(component_os)? {
                     (0..n_dots).for_each(|_| push_to_prefix(ParentDir));
                 } else {
                     break;
                 }
             }
+            None => break,
         }
 
-        let _consumed = components.next();
+        components = rest;
     }
 
-    Ok((prefix, components))
+    Ok((prefix, components.as_path()))
+}
+
+/// Checks if the path starts with a root or a drive, e.g. `/` or `c:`.
+fn is_anchored(path: &Path) -> bool {
+    matches!(
+        path.components().next(),
+        Some(Component::Prefix(_) | Component::RootDir)
+    )
+}
+
+/// Splits the rest of the arg into components and converts each one into
+/// [`Abbr`](Abbr). Separators inside curly braces don't split the component
+/// so that they can be used in regexes.
+fn parse_abbrs(rest: &Path) -> Result<Vec<Abbr>, Error> {
+    if is_anchored(rest) {
+        return Err(Error::UnexpectedAbbrComponent(
+            rest.to_string_lossy().into_owned(),
+        ));
+    }
+
+    let rest = rest.to_str().ok_or(Error::NonUnicodeInput)?;
+
+    abbr::split_components(rest)
+        .into_iter()
+        .filter(|component| !component.is_empty() && *component != ".")
+        .map(|component| {
+            if component == ".." {
+                Err(Error::UnexpectedAbbrComponent(component.to_string()))
+            } else {
+                Abbr::parse(component)
+            }
+        })
+        .collect()
 }
 
-/// Converts each component into [`Abbr`](Abbr) without checking
-/// the component's type.
+/// Parses the provided args into a prefix and [`Abbr`](Abbr)'s.
 ///
-/// This may change in the future.
-fn parse_abbrs<'a, I>(components: I) -> Result<Vec<Abbr>, Error>
+/// Each arg is split into its own components. The prefix may continue in the
+/// following args as long as no abbreviation has been parsed, e.g. `..` and
+/// `...` are the same as `../...`, but only the first arg may be absolute.
+fn parse_args<P>(args: &[P]) -> Result<(Option<PathBuf>, Vec<Abbr>), Error>
 where
-    I: Iterator<Item = Component<'a>> + 'a,
+    P: AsRef<Path>,
 {
-    use Component::*;
+    let mut prefix: Option<PathBuf> = None;
+    let mut abbrs = vec![];
 
-    let abbrs = components
-        .into_iter()
-        .map(|component| match component {
-            Prefix(_) | RootDir | CurDir | ParentDir => {
-                let component_string = component
-                    .as_os_str()
-                    .to_os_string()
-                    .to_string_lossy()
-                    .to_string();
-
-                Err(Error::UnexpectedAbbrComponent(component_string))
+    for arg in args {
+        let rest = if abbrs.is_empty() {
+            let (arg_prefix, rest) = extract_prefix(arg)?;
+
+            match (&mut prefix, arg_prefix) {
+                (Some(prefix), Some(arg_prefix))
+                    if !is_anchored(&arg_prefix) =>
+                    prefix.push(arg_prefix),
+                (Some(_), Some(arg_prefix)) =>
+                    return Err(Error::UnexpectedAbbrComponent(
+                        arg_prefix.to_string_lossy().into_owned(),
+                    )),
+                (None, arg_prefix) => prefix = arg_prefix,
+                (Some(_), None) => {}
             }
-            Normal(component_os) => component_os
-                .to_os_string()
-                .into_string()
-                .map_err(|_| Error::NonUnicodeInput)
-                .map(|string| Abbr::new_sanitized(&string)),
-        })
-        .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(abbrs)
+            rest
+        } else {
+            arg.as_ref()
+        };
+
+        abbrs.extend(parse_abbrs(rest)?);
+    }
+
+    Ok((prefix, abbrs))
 }
 
-/// Parses the provided argument into a prefix and [`Abbr`](Abbr)'s.
-fn parse_arg<P>(arg: &P) -> Result<(Option<PathBuf>, Vec<Abbr>), Error>
+/// Joins the args into a path, if they are relative to each other, e.g. `foo`
+/// and `bar` into `foo/bar`.
+fn join_args<P>(args: &[P]) -> Option<PathBuf>
 where
     P: AsRef<Path>,
 {
-    let (prefix, suffix) = extract_prefix(arg)?;
-    let abbrs = parse_abbrs(suffix)?;
+    let (first, rest) = args.split_first()?;
+    let mut path = first.as_ref().to_path_buf();
 
-    Ok((prefix, abbrs))
+    for arg in rest {
+        let arg = arg.as_ref();
+
+        if is_anchored(arg) || arg.components().next().is_none() {
+            return None;
+        }
+
+        path.push(arg);
+    }
+
+    Some(path)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    use crate::utils::as_path;
+    use crate::{
+        abbr::Congruence,
+        listing::{DirReader, MemoryTree},
+    };
+
+    /// Shorthand for `AsRef<Path>::as_ref(&x)`.
+    fn as_path<P>(path: &P) -> &Path
+    where
+        P: AsRef<Path> + ?Sized,
+    {
+        path.as_ref()
+    }
 
     #[test]
     fn test_parse_dots() {
@@ -276,26 +1897,23 @@ mod test {
     fn test_extract_prefix() {
         {
             let (prefix, suffix) = extract_prefix("suf/fix").unwrap();
-            let suffix = suffix.collect::<PathBuf>();
 
             assert_eq!(prefix, None);
-            assert_eq!(as_path(&suffix), as_path("suf/fix"));
+            assert_eq!(suffix, as_path("suf/fix"));
         }
 
         {
             let (prefix, suffix) = extract_prefix("./.././suf/fix").unwrap();
-            let suffix = suffix.collect::<PathBuf>();
 
             assert_eq!(prefix.unwrap(), as_path("./.."));
-            assert_eq!(as_path(&suffix), as_path("suf/fix"));
+            assert_eq!(suffix, as_path("suf/fix"));
         }
 
         {
             let (prefix, suffix) = extract_prefix(".../.../suf/fix").unwrap();
-            let suffix = suffix.collect::<PathBuf>();
 
             assert_eq!(prefix.unwrap(), as_path("../../../.."));
-            assert_eq!(as_path(&suffix), as_path("suf/fix"));
+            assert_eq!(suffix, as_path("suf/fix"));
         }
     }
 
@@ -309,20 +1927,1150 @@ mod test {
             let source = [0x66, 0x6f, 0x80, 0x6f];
             let non_unicode_input =
                 OsStr::from_bytes(&source[..]).to_os_string();
-            let result = parse_arg(&non_unicode_input);
+            let result = parse_args(&[non_unicode_input]);
 
             assert!(result.is_err());
         }
 
         #[cfg(windows)]
         {
+            use std::ffi::OsString;
             use std::os::windows::prelude::*;
 
             let source = [0x0066, 0x006f, 0xd800, 0x006f];
             let os_string = OsString::from_wide(&source[..]);
-            let result = parse_arg(&non_unicode_input);
+            let result = parse_args(&[non_unicode_input]);
 
             assert!(result.is_err());
         }
     }
+
+    #[test]
+    fn test_congruences_ordering() {
+        let root = Congruences(None);
+        let parent_a = root.push(1);
+        let parent_b = root.push(2);
+
+        // The closest ancestor is compared first.
+        assert!(parent_b.push(1) < parent_a.push(2));
+        assert!(parent_a.push(1) < parent_a.push(2));
+        assert!(parent_a.push(1) < parent_b.push(1));
+        assert_eq!(parent_a.push(1), root.push(1).push(1));
+        assert_eq!(parent_a.push(1).first(), Some(&1));
+        assert_eq!(root.first(), None);
+    }
+
+    #[test]
+    fn test_keep_best() {
+        let finding = |name: &str, congruence: u32| Finding {
+            path: as_path(name).into(),
+            congruences: Congruences(None).push(congruence),
+        };
+        let mut level = vec![
+            finding("c", 1),
+            finding("b", 0),
+            finding("a", 1),
+            finding("d", 2),
+        ];
+
+        assert!(!keep_best(&mut level, None));
+        assert!(!keep_best(&mut level, Some(4)));
+        assert_eq!(level.len(), 4);
+
+        assert!(keep_best(&mut level, Some(2)));
+        assert_variant!(
+            level.as_slice(),
+            [Finding { path: b, .. }, Finding { path: a, .. }]
+                if b == as_path("b") && a == as_path("a")
+        );
+    }
+
+    #[test]
+    fn test_expand_level_deterministic() {
+        use crate::matcher::DefaultMatcher;
+
+        let root = tempfile::tempdir().unwrap();
+        let parents = (0..8)
+            .map(|i| {
+                let path = root.path().join(format!("parent-{}", i));
+                for name in ["foo", "foo-bar", "fob", "baz"] {
+                    std::fs::create_dir_all(path.join(name)).unwrap();
+                }
+                // Files are skipped.
+                std::fs::write(path.join("foo.txt"), "").unwrap();
+
+                Finding {
+                    path,
+                    congruences: Congruences(None),
+                }
+            })
+            .collect::<Vec<_>>();
+        let abbr = Abbr::parse("fo").unwrap();
+        let matcher = DefaultMatcher::default();
+
+        let paths = |threads| {
+            expand_level(
+                &parents,
+                &abbr,
+                &matcher,
+                threads,
+                &Filter::default(),
+                &DirReader,
+                &|_| {},
+                &AtomicBool::new(false),
+            )
+            .into_iter()
+            .map(|finding| finding.path)
+            .collect::<Vec<_>>()
+        };
+        let sequential = paths(1);
+
+        assert_eq!(sequential.len(), 24);
+        for threads in 2..=10 {
+            assert_eq!(paths(threads), sequential);
+        }
+    }
+
+    #[test]
+    fn test_search_with_timeout() {
+        use crate::matcher::DefaultMatcher;
+
+        let root = tempfile::tempdir().unwrap();
+        for path in ["foo/bar", "foo/baz", "fob/bar", "qux/bar"] {
+            std::fs::create_dir_all(root.path().join(path)).unwrap();
+        }
+        let abbrs =
+            vec![Abbr::parse("fo").unwrap(), Abbr::parse("ba").unwrap()];
+        let matcher = DefaultMatcher::default();
+
+        let paths = |outcome: Outcome<_>| {
+            let mut paths = outcome
+                .findings
+                .into_iter()
+                .map(|finding| finding.path)
+                .collect::<Vec<_>>();
+            paths.sort();
+
+            paths
+        };
+
+        let outcome = search(
+            root.path().into(),
+            &abbrs,
+            &matcher,
+            &Avoided::default(),
+            Limits::default(),
+            1,
+            &Filter::default(),
+            &DirReader,
+            &|_| {},
+            &AtomicBool::new(false),
+            false,
+        );
+        assert!(!outcome.truncated);
+        let expected = paths(outcome);
+        assert_eq!(expected.len(), 3);
+
+        let outcome = search_with_timeout(
+            root.path().into(),
+            abbrs,
+            matcher,
+            Avoided::default(),
+            Limits::default(),
+            1,
+            Filter::default(),
+            Arc::new(DirReader),
+            Duration::from_secs(60),
+            false,
+        );
+        assert!(!outcome.timed_out);
+        assert!(!outcome.truncated);
+        assert_eq!(paths(outcome), expected);
+    }
+
+    #[test]
+    fn test_search_cancelled_on_timeout() {
+        use std::sync::atomic::AtomicUsize;
+
+        /// Lists dirs kept in memory and counts the listings. The listing of
+        /// `/d0` is blocked until it's released through the channel.
+        struct BlockedLister(
+            MemoryTree,
+            AtomicUsize,
+            Mutex<mpsc::Receiver<()>>,
+        );
+
+        impl Lister for BlockedLister {
+            fn list(&self, dir: &Path) -> Vec<Entry> {
+                self.1.fetch_add(1, AtomicOrdering::SeqCst);
+
+                if dir == Path::new("/d0") {
+                    let _ = self.2.lock().unwrap().recv();
+                }
+
+                self.0.list(dir)
+            }
+        }
+
+        let tree = (0..10)
+            .flat_map(|i| (0..10).map(move |j| format!("/d{}/d{}", i, j)))
+            .fold(MemoryTree::new(), MemoryTree::dir);
+        let (release, blocked) = mpsc::channel();
+        let lister = Arc::new(BlockedLister(
+            tree,
+            AtomicUsize::new(0),
+            Mutex::new(blocked),
+        ));
+        let options = QueryOptions::new()
+            .start_dir("/")
+            .threads(1)
+            .timeout(Duration::from_millis(10))
+            .lister(lister.clone());
+
+        assert_variant!(
+            Query::with_options("d/d/x", options).find(),
+            Err(Error::Timeout)
+        );
+        release.send(()).unwrap();
+
+        // Wait for the search thread to finish and drop the lister.
+        while Arc::strong_count(&lister) > 1 {
+            thread::yield_now();
+        }
+
+        // The search thread stops reading dirs, only the listing in progress
+        // is completed.
+        assert_eq!(lister.1.load(AtomicOrdering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_one_file_system() {
+        use crate::matcher::DefaultMatcher;
+
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("foo/bar")).unwrap();
+        let abbrs =
+            vec![Abbr::parse("fo").unwrap(), Abbr::parse("ba").unwrap()];
+        let filter =
+            Filter::new(root.path(), true, Hidden::Include, true, &DirReader);
+        assert!(filter.device.is_some() || cfg!(not(unix)));
+
+        // Every dir is on the same device as the start dir.
+        let outcome = search(
+            root.path().into(),
+            &abbrs,
+            &DefaultMatcher::default(),
+            &Avoided::default(),
+            Limits::default(),
+            1,
+            &filter,
+            &DirReader,
+            &|_| {},
+            &AtomicBool::new(false),
+            false,
+        );
+        assert_variant!(
+            outcome.findings.as_slice(),
+            [Finding { path, .. }] if path == &root.path().join("foo/bar")
+        );
+
+        #[cfg(unix)]
+        {
+            let path = root.path().join("foo");
+            let device = DirReader.device(&path).unwrap();
+
+            assert!(is_on_device(&DirReader, &path, device));
+            assert!(!is_on_device(&DirReader, &path, device.wrapping_add(1)));
+        }
+    }
+
+    #[test]
+    fn test_tiebreaks_with_lister() {
+        use crate::listing::Metadata;
+        use std::time::SystemTime;
+
+        /// Reports the dirs under `/r/b-new` as modified later than others.
+        struct TimedLister(MemoryTree);
+
+        impl Lister for TimedLister {
+            fn list(&self, dir: &Path) -> Vec<Entry> {
+                self.0.list(dir)
+            }
+
+            fn metadata(&self, path: &Path) -> Metadata {
+                let modified = if path.starts_with("/r/b-new") {
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(1)
+                } else {
+                    SystemTime::UNIX_EPOCH
+                };
+
+                Metadata {
+                    modified: Some(modified),
+                    accessed: None,
+                }
+            }
+        }
+
+        let tree = MemoryTree::new().dir("/r/a-old/kn").dir("/r/b-new/kn");
+        let options = QueryOptions::new()
+            .start_dir("/r")
+            .lister(Arc::new(TimedLister(tree)));
+        let find =
+            |options| Query::with_options("-/kn", options).find().unwrap().path;
+
+        assert_eq!(find(options.clone()), Path::new("/r/a-old/kn"));
+        assert_eq!(
+            find(options.tiebreaks(vec![Tiebreak::Mtime])),
+            Path::new("/r/b-new/kn")
+        );
+    }
+
+    #[test]
+    fn test_depth_reads_dirs_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        /// Counts the reads of the dirs.
+        struct CountingLister(MemoryTree, AtomicUsize);
+
+        impl Lister for CountingLister {
+            fn list(&self, dir: &Path) -> Vec<Entry> {
+                self.1.fetch_add(1, AtomicOrdering::Relaxed);
+
+                self.0.list(dir)
+            }
+        }
+
+        let tree = MemoryTree::new()
+            .dir("/r/a/b/c")
+            .dir("/r/a/d")
+            .dir("/r/e/f");
+        let lister = Arc::new(CountingLister(tree, AtomicUsize::new(0)));
+        let options = QueryOptions::new()
+            .start_dir("/r")
+            .depth(3)
+            .lister(lister.clone());
+
+        assert_variant!(
+            Query::with_options("zzz", options).find(),
+            Err(Error::PathNotFound)
+        );
+        // `/r`, `/r/a`, `/r/e`, `/r/a/b`, `/r/a/d` and `/r/e/f`, rather than
+        // 10 reads by three separate searches.
+        assert_eq!(lister.1.load(AtomicOrdering::Relaxed), 6);
+    }
+
+    #[test]
+    fn test_matches_tiebreak_keys() {
+        use crate::listing::Metadata;
+        use std::sync::atomic::AtomicUsize;
+
+        /// Counts the reads of the dirs' metadata.
+        struct CountingLister(MemoryTree, AtomicUsize);
+
+        impl Lister for CountingLister {
+            fn list(&self, dir: &Path) -> Vec<Entry> {
+                self.0.list(dir)
+            }
+
+            fn metadata(&self, _path: &Path) -> Metadata {
+                self.1.fetch_add(1, AtomicOrdering::Relaxed);
+
+                Metadata::default()
+            }
+        }
+
+        let tree = MemoryTree::new()
+            .dir("/r/kn")
+            .dir("/r/kn-a")
+            .dir("/r/kn-b")
+            .dir("/r/k-n");
+        let lister = Arc::new(CountingLister(tree, AtomicUsize::new(0)));
+        let options = QueryOptions::new()
+            .start_dir("/r")
+            .tiebreaks(vec![Tiebreak::Mtime])
+            .lister(lister.clone());
+        let paths = |options| {
+            Query::with_options("kn", options)
+                .matches()
+                .unwrap()
+                .map(|found| found.path)
+                .collect::<Vec<_>>()
+        };
+
+        // Only the tied dirs are compared with the tiebreakers.
+        assert_eq!(
+            paths(options.clone()),
+            [
+                Path::new("/r/kn"),
+                Path::new("/r/kn-a"),
+                Path::new("/r/kn-b"),
+                Path::new("/r/k-n"),
+            ]
+        );
+        assert_eq!(lister.1.swap(0, AtomicOrdering::Relaxed), 2);
+
+        // The ties after the limit are not compared.
+        assert_eq!(paths(options.limit(1)), [Path::new("/r/kn")]);
+        assert_eq!(lister.1.load(AtomicOrdering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_current_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let current_dir = root.path().join("cwd");
+        for path in ["cwd/kn", "cwd/knot", "other/kn"] {
+            std::fs::create_dir_all(root.path().join(path)).unwrap();
+        }
+        let options = || QueryOptions::new().current_dir(&current_dir);
+        let find = |abbr, options| {
+            Query::with_options(abbr, options)
+                .find()
+                .map(|found| found.path)
+        };
+
+        // Relative paths are resolved against the current dir, not the
+        // process's one.
+        assert_eq!(find("kn", options()).unwrap(), current_dir.join("kn"));
+        assert_eq!(find("kno", options()).unwrap(), current_dir.join("knot"));
+        assert_eq!(
+            find("../ot/k", options()).unwrap(),
+            current_dir.join("../other/kn")
+        );
+        assert_eq!(
+            find("k", options().start_dir("../other")).unwrap(),
+            current_dir.join("../other/kn")
+        );
+        assert_eq!(
+            find("k", options().avoid("kn")).unwrap(),
+            current_dir.join("knot")
+        );
+        assert_variant!(
+            find("k", options().exclude("./kn").exclude("knot")),
+            Err(Error::PathNotFound)
+        );
+    }
+
+    #[test]
+    fn test_one_file_system_devices() {
+        /// Puts the dirs under `/kn/mnt-dev` on another device.
+        struct DeviceLister(MemoryTree);
+
+        impl Lister for DeviceLister {
+            fn list(&self, dir: &Path) -> Vec<Entry> {
+                self.0.list(dir)
+            }
+
+            fn device(&self, path: &Path) -> Option<u64> {
+                Some(if path.starts_with("/kn/mnt-dev") {
+                    2
+                } else {
+                    1
+                })
+            }
+        }
+
+        let tree = MemoryTree::new()
+            .dir("/kn/mnt/kn")
+            .dir("/kn/mnt-dev/kn")
+            .symlink("/kn/mnt-link", "/kn/mnt");
+        let paths = |one_file_system| {
+            let options = QueryOptions::new()
+                .start_dir("/kn")
+                .lister(Arc::new(DeviceLister(tree.clone())))
+                .limits(Limits {
+                    one_file_system,
+                    ..Limits::default()
+                });
+            let mut paths = memory_paths(Query::with_options("mn/kn", options));
+            paths.sort();
+
+            paths
+        };
+
+        assert_eq!(
+            paths(false),
+            [
+                as_path("/kn/mnt/kn"),
+                as_path("/kn/mnt-dev/kn"),
+                as_path("/kn/mnt-link/kn"),
+            ]
+        );
+
+        // Neither the dirs on other devices nor symlinks are searched.
+        assert_eq!(paths(true), [as_path("/kn/mnt/kn")]);
+    }
+
+    #[test]
+    fn test_search_same_as_exhaustive() {
+        use crate::matcher::DefaultMatcher;
+
+        let root = tempfile::tempdir().unwrap();
+        for path in [
+            "foo/bar",
+            "foo/barbaz",
+            "foo/qux/bar",
+            "foobar/bar",
+            "fo/bar",
+            "fo/ba",
+            "f-o/b-a-r",
+            "qux/foo/bar",
+        ] {
+            std::fs::create_dir_all(root.path().join(path)).unwrap();
+        }
+        let matcher = DefaultMatcher::default();
+        let best_path = |outcome: Outcome<Congruence>, avoided: &Path| {
+            select_best(
+                outcome
+                    .findings
+                    .into_iter()
+                    .filter(|finding| finding.path != avoided),
+                &[],
+                &DirReader,
+            )
+            .map(|finding| finding.path)
+        };
+
+        for abbr in [
+            "foo/bar",
+            "fo/ba",
+            "fo/bar",
+            "f/b",
+            "-/bar",
+            "foo/-/bar",
+            "fb/ba",
+            "qux",
+        ] {
+            let abbrs = abbr
+                .split('/')
+                .map(|abbr| Abbr::parse(abbr).unwrap())
+                .collect::<Vec<_>>();
+
+            for avoided in ["", "foo/bar", "fo/ba", "fo/bar"] {
+                let avoided = root.path().join(avoided);
+                let search_exhaustively = || {
+                    let search = Search {
+                        abbrs: &abbrs,
+                        matcher: &matcher,
+                        avoided: &Avoided::default(),
+                        limits: Limits::default(),
+                        threads: 1,
+                        filter: &Filter::default(),
+                        lister: &DirReader,
+                        report: &|_| {},
+                        cancelled: &AtomicBool::new(false),
+                    };
+                    let mut read_dirs =
+                        abbrs.iter().map(|_| HashMap::new()).collect();
+
+                    search.levels(root.path(), false, &mut read_dirs, &mut 0)
+                };
+                let exhaustive = best_path(search_exhaustively(), &avoided);
+                let outcome = search(
+                    root.path().into(),
+                    &abbrs,
+                    &matcher,
+                    &Avoided::new(root.path(), None, [&avoided]).unwrap(),
+                    Limits::default(),
+                    1,
+                    &Filter::default(),
+                    &DirReader,
+                    &|_| {},
+                    &AtomicBool::new(false),
+                    false,
+                );
+
+                assert_eq!(
+                    best_path(outcome, &avoided),
+                    exhaustive,
+                    "{} avoiding {}",
+                    abbr,
+                    avoided.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        let root = tempfile::tempdir().unwrap();
+        for path in ["foo/bar", "foo/barbaz", "fob/bax", "qux"] {
+            std::fs::create_dir_all(root.path().join(path)).unwrap();
+        }
+        let options = || QueryOptions::new().start_dir(root.path());
+        let paths = |matches: Matches<Congruence>| {
+            matches
+                .map(|found| {
+                    found.path.strip_prefix(root.path()).unwrap().into()
+                })
+                .collect::<Vec<PathBuf>>()
+        };
+
+        let query = Query::with_options("fo/ba", options());
+        assert_eq!(query.find().unwrap().path, root.path().join("foo/bar"));
+        assert_eq!(
+            paths(query.matches().unwrap()),
+            [
+                as_path("foo/bar"),
+                as_path("fob/bax"),
+                as_path("foo/barbaz"),
+            ]
+        );
+        assert_variant!(
+            query.matches().unwrap().last(),
+            Some(Match { scores, .. })
+                if scores == [Congruence::Prefix(1), Congruence::Prefix(4)]
+        );
+
+        let query = Query::with_options("fo/ba", options().limit(2));
+        assert_eq!(query.matches().unwrap().len(), 2);
+
+        let query = Query::with_options(
+            "fo/ba",
+            options()
+                .avoid(root.path().join("foo/bar"))
+                .avoid(root.path().join("fob/bax")),
+        );
+        assert_eq!(query.find().unwrap().path, root.path().join("foo/barbaz"));
+        assert_eq!(paths(query.matches().unwrap()), [as_path("foo/barbaz")]);
+
+        // The avoided path is returned if it's the only match.
+        let query =
+            Query::with_options("qu", options().avoid(root.path().join("qux")));
+        assert_eq!(query.find().unwrap().path, root.path().join("qux"));
+
+        let query = Query::with_options("zzz", options());
+        assert_variant!(query.find(), Err(Error::PathNotFound));
+        assert_eq!(query.matches().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_query_matches_exhaustive() {
+        let root = tempfile::tempdir().unwrap();
+        for path in ["foo/bar", "foo/barbaz", "foox/bar"] {
+            std::fs::create_dir_all(root.path().join(path)).unwrap();
+        }
+        let options = || QueryOptions::new().start_dir(root.path());
+
+        // `foo/bar` matches completely, yet the other paths are returned too.
+        let matches = Query::with_options("=foo/bar", options())
+            .matches()
+            .unwrap()
+            .map(|found| found.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            matches,
+            [root.path().join("foo/bar"), root.path().join("foo/barbaz")]
+        );
+
+        let matches = Query::with_options("foo", options()).matches().unwrap();
+        assert_variant!(
+            matches.collect::<Vec<_>>().as_slice(),
+            [Match { path, scores }]
+                if path == &root.path().join("foo") && scores.is_empty()
+        );
+    }
+
+    #[test]
+    fn test_query_options() {
+        let root = tempfile::tempdir().unwrap();
+        for path in ["src/foo", "a/b/src/bar", "a/src", ".hidden/src/baz"] {
+            std::fs::create_dir_all(root.path().join(path)).unwrap();
+        }
+        let options = || QueryOptions::new().start_dir(root.path());
+        let paths = |query: Query| {
+            query
+                .matches()
+                .unwrap()
+                .map(|found| {
+                    found.path.strip_prefix(root.path()).unwrap().into()
+                })
+                .collect::<Vec<PathBuf>>()
+        };
+
+        assert_eq!(
+            paths(Query::with_options("sr/-", options())),
+            [as_path("src/foo")]
+        );
+
+        // The shallowest path comes first.
+        assert_eq!(
+            paths(Query::with_options("sr/-", options().depth(3))),
+            [
+                as_path("src/foo"),
+                as_path(".hidden/src/baz"),
+                as_path("a/b/src/bar"),
+            ]
+        );
+        assert_eq!(
+            paths(Query::with_options(
+                "sr/-",
+                options().depth(3).hidden(Hidden::Skip)
+            )),
+            [as_path("src/foo"), as_path("a/b/src/bar")]
+        );
+        assert_eq!(
+            paths(Query::with_options("hi/sr", options())),
+            [as_path(".hidden/src")]
+        );
+        assert!(paths(Query::with_options(
+            "hi/sr",
+            options().hidden(Hidden::Skip)
+        ))
+        .is_empty());
+
+        // A relative prefix is relative to the start dir.
+        let query = Query::with_options(
+            "../sr",
+            QueryOptions::new().start_dir(root.path().join("a/b")),
+        );
+        assert_eq!(query.find().unwrap().path, root.path().join("a/b/../src"));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(
+                root.path().join("a/b"),
+                root.path().join("link"),
+            )
+            .unwrap();
+
+            assert_eq!(
+                paths(Query::with_options("li/sr", options())),
+                [as_path("link/src")]
+            );
+            assert!(paths(Query::with_options(
+                "li/sr",
+                options().follow_symlinks(false)
+            ))
+            .is_empty());
+        }
+    }
+
+    fn memory_options(tree: MemoryTree) -> QueryOptions {
+        QueryOptions::new().start_dir("/").lister(Arc::new(tree))
+    }
+
+    fn memory_paths(query: Query) -> Vec<PathBuf> {
+        query.matches().unwrap().map(|found| found.path).collect()
+    }
+
+    #[test]
+    fn test_memory_ranking() {
+        let tree = MemoryTree::new()
+            .dir("/projects/kn")
+            .dir("/projects/knot")
+            .dir("/private/kn-old")
+            .dir("/prj/kn")
+            .dir("/pr/karton");
+        let query = Query::with_options("pr/kn", memory_options(tree));
+
+        // The last components are compared first.
+        assert_eq!(
+            memory_paths(query.clone()),
+            [
+                as_path("/prj/kn"),
+                as_path("/projects/kn"),
+                as_path("/projects/knot"),
+                as_path("/private/kn-old"),
+                as_path("/pr/karton"),
+            ]
+        );
+        assert_variant!(
+            query.matches().unwrap().next(),
+            Some(Match { scores, .. })
+                if scores == [Congruence::Prefix(1), Congruence::Complete]
+        );
+        assert_eq!(query.find().unwrap().path, as_path("/prj/kn"));
+    }
+
+    #[test]
+    fn test_memory_args() {
+        let tree = MemoryTree::new()
+            .dir("/projects/kn/src")
+            .dir("/projects/knot")
+            .dir("/other/kn-old");
+        let options = || memory_options(tree.clone());
+        let nested = || options().start_dir("/projects/kn/src");
+
+        // Each arg is split into its own components.
+        assert_eq!(
+            memory_paths(Query::with_args(["...", "kn"], nested())),
+            [
+                as_path("/projects/kn/src/../../kn"),
+                as_path("/projects/kn/src/../../knot"),
+            ]
+        );
+        assert_eq!(
+            memory_paths(Query::with_args(["..", "..", "k"], nested())),
+            [
+                as_path("/projects/kn/src/../../kn"),
+                as_path("/projects/kn/src/../../knot"),
+            ]
+        );
+        assert_eq!(
+            memory_paths(Query::with_args(["pr", "{^k[^/]+t$}"], options())),
+            [as_path("/projects/knot")]
+        );
+        assert_eq!(
+            memory_paths(Query::with_args(["pr/kn", "s"], options())),
+            [as_path("/projects/kn/src")]
+        );
+        assert_eq!(
+            memory_paths(Query::with_args(["projects", "kn"], options())),
+            [as_path("/projects/kn")]
+        );
+
+        // Only the first arg may be absolute and the prefix can't follow
+        // abbreviations.
+        for args in [["kn", "/projects"], ["kn", "../k"], ["..", "/projects"]] {
+            assert_variant!(
+                Query::with_args(args, options()).find(),
+                Err(Error::UnexpectedAbbrComponent(_))
+            );
+        }
+    }
+
+    #[test]
+    fn test_memory_max_dirs() {
+        let limits = Limits {
+            max_dirs: Some(2),
+            ..Limits::default()
+        };
+
+        // The dirs are listed in the order they're added. Only one dir on the
+        // second level can be read.
+        for tree in [
+            MemoryTree::new().dir("/xyz-old/kn").dir("/x/kn"),
+            MemoryTree::new().dir("/x/kn").dir("/xyz-old/kn"),
+        ] {
+            let options = memory_options(tree).limits(limits);
+            let matches =
+                Query::with_options("x/k", options).matches().unwrap();
+
+            assert!(matches.truncated());
+            assert_eq!(
+                matches.map(|found| found.path).collect::<Vec<_>>(),
+                [as_path("/x/kn")]
+            );
+        }
+    }
+
+    #[test]
+    fn test_memory_wildcards() {
+        let tree = MemoryTree::new()
+            .dir("/a/x/kn")
+            .dir("/b/kn/deep")
+            .dir("/c/knot");
+        let options = || memory_options(tree.clone());
+
+        assert_eq!(
+            memory_paths(Query::with_options("-/kn", options())),
+            [as_path("/b/kn"), as_path("/c/knot")]
+        );
+        assert_eq!(
+            memory_paths(Query::with_options("-/-/kn", options())),
+            [as_path("/a/x/kn")]
+        );
+        assert_eq!(
+            memory_paths(Query::with_options("kn", options().depth(3))),
+            [as_path("/b/kn"), as_path("/a/x/kn"), as_path("/c/knot")]
+        );
+    }
+
+    #[test]
+    fn test_memory_avoided() {
+        let tree = MemoryTree::new()
+            .dir("/kn/src")
+            .dir("/kn/source")
+            .dir("/knot/src");
+        let options = || memory_options(tree.clone());
+
+        let query = Query::with_options("kn/=src", options());
+        assert_eq!(query.find().unwrap().path, as_path("/kn/src"));
+
+        // The best match is avoided, the rest of the tree is searched.
+        let query = Query::with_options("kn/=src", options().avoid("/kn/src"));
+        assert_eq!(query.find().unwrap().path, as_path("/knot/src"));
+        assert_eq!(memory_paths(query), [as_path("/knot/src")]);
+
+        // Avoided paths are returned if there are no other matches.
+        let query = Query::with_options(
+            "kn/=src",
+            options().avoid("/kn/src").avoid("/knot/src"),
+        );
+        assert_eq!(query.find().unwrap().path, as_path("/kn/src"));
+        assert_eq!(
+            memory_paths(query),
+            [as_path("/kn/src"), as_path("/knot/src")]
+        );
+    }
+
+    #[test]
+    fn test_memory_exclusion() {
+        let tree = MemoryTree::new()
+            .dir("/app/src")
+            .dir("/app/node_modules/lib/src")
+            .dir("/app/vendor/lib/src")
+            .dir("/kn/src")
+            .dir("/knot/src");
+        let options = || memory_options(tree.clone());
+        let pattern = |pattern| Pattern::new(pattern).unwrap();
+
+        // Unlike avoided paths, excluded ones are never returned.
+        let query = Query::with_options(
+            "kn/=src",
+            options().exclude("/kn/src").exclude("/knot/src"),
+        );
+        assert_variant!(query.find(), Err(Error::PathNotFound));
+
+        // Excluded dirs' subtrees are not searched.
+        let query = Query::with_options("kn/=src", options().exclude("/kn"));
+        assert_eq!(memory_paths(query), [as_path("/knot/src")]);
+
+        let query = Query::with_options("=src", options().depth(4));
+        assert_eq!(
+            memory_paths(query),
+            [
+                as_path("/app/src"),
+                as_path("/kn/src"),
+                as_path("/knot/src"),
+                as_path("/app/node_modules/lib/src"),
+                as_path("/app/vendor/lib/src"),
+            ]
+        );
+        let query = Query::with_options(
+            "=src",
+            options()
+                .depth(4)
+                .exclude(pattern("node_modules"))
+                .exclude(pattern("/*/vendor"))
+                .exclude(pattern("kn*")),
+        );
+        assert_eq!(memory_paths(query), [as_path("/app/src")]);
+
+        // The start dir itself is not excluded.
+        let query = Query::with_options(
+            "-/lib",
+            options().start_dir("/app").exclude("/app"),
+        );
+        assert_eq!(
+            memory_paths(query),
+            [as_path("/app/node_modules/lib"), as_path("/app/vendor/lib"),]
+        );
+    }
+
+    #[test]
+    fn test_memory_symlinks() {
+        let tree = MemoryTree::new()
+            .dir("/a/b/src")
+            .symlink("/link", "/a/b")
+            .symlink("/a/rel", "b")
+            .symlink("/a/loop", "/a");
+        let options = || memory_options(tree.clone());
+
+        assert_eq!(
+            memory_paths(Query::with_options("li/sr", options())),
+            [as_path("/link/src")]
+        );
+        assert_eq!(
+            memory_paths(Query::with_options("a/re/sr", options())),
+            [as_path("/a/rel/src")]
+        );
+        assert_eq!(
+            memory_paths(Query::with_options("a/lo/lo/b/sr", options())),
+            [as_path("/a/loop/loop/b/src")]
+        );
+        assert!(memory_paths(Query::with_options(
+            "li/sr",
+            options().follow_symlinks(false)
+        ))
+        .is_empty());
+
+        // Real paths are returned as they are.
+        let matches = Query::with_options("link/src", options())
+            .matches()
+            .unwrap();
+        assert_variant!(
+            matches.collect::<Vec<_>>().as_slice(),
+            [Match { path, scores }]
+                if path == as_path("/link/src") && scores.is_empty()
+        );
+    }
+
+    #[test]
+    fn test_exclusion_parse() {
+        assert_variant!(
+            Exclusion::parse(OsStr::new("./foo")),
+            Ok(Exclusion::Path(path)) if path == as_path("./foo")
+        );
+        assert_variant!(
+            Exclusion::parse(OsStr::new("..")),
+            Ok(Exclusion::Path(path)) if path == as_path("..")
+        );
+        assert_variant!(
+            Exclusion::parse(OsStr::new("node_modules")),
+            Ok(Exclusion::Pattern(pattern))
+                if pattern.as_str() == "node_modules"
+        );
+        assert_variant!(
+            Exclusion::parse(OsStr::new("*/target")),
+            Ok(Exclusion::Pattern(pattern)) if pattern.as_str() == "*/target"
+        );
+        assert_variant!(
+            Exclusion::parse(OsStr::new("[a")),
+            Err(Error::InvalidPattern(_))
+        );
+
+        assert_variant!(
+            Exclusion::from("node_modules"),
+            Exclusion::Pattern(pattern) if pattern.as_str() == "node_modules"
+        );
+        assert_variant!(
+            Exclusion::from("./foo"),
+            Exclusion::Path(path) if path == as_path("./foo")
+        );
+        assert_variant!(
+            Exclusion::from("[a"),
+            Exclusion::Path(path) if path == as_path("[a")
+        );
+    }
+
+    #[test]
+    fn test_exclusion_normalized() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        for path in ["kn/src", "knot/src"] {
+            fs::create_dir_all(root.join(path)).unwrap();
+        }
+        let find = |options: QueryOptions| {
+            Query::with_options("kn/=src", options).find().unwrap().path
+        };
+
+        let options = QueryOptions::new().start_dir(&root);
+        assert_eq!(
+            find(options.clone().exclude(root.join("knot/../kn/."))),
+            root.join("knot/src")
+        );
+
+        // The start dir is normalized too.
+        let options = QueryOptions::new().start_dir(root.join("knot/.."));
+        assert_eq!(
+            find(options.exclude(root.join("kn"))),
+            root.join("knot/../knot/src")
+        );
+
+        // Relative paths are relative to the current dir.
+        #[cfg(unix)]
+        {
+            let options = QueryOptions::new().start_dir(&root);
+            assert_eq!(
+                find(options.exclude(relative(&root.join("kn")))),
+                root.join("knot/src")
+            );
+        }
+    }
+
+    /// Returns the relative path from the current dir to the absolute path.
+    #[cfg(unix)]
+    fn relative(path: &Path) -> PathBuf {
+        env::current_dir()
+            .unwrap()
+            .components()
+            .skip(1)
+            .map(|_| Component::ParentDir)
+            .collect::<PathBuf>()
+            .join(path.strip_prefix("/").unwrap())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_relative_prefix() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        for path in ["kn/src", "knot/src"] {
+            fs::create_dir_all(root.join(path)).unwrap();
+        }
+        // The arg starts with `../` repeated up to the root, the rest of the
+        // components are matched exactly.
+        let arg = relative(&root).join("kn/=src");
+        assert!(arg.starts_with(".."));
+        let find = |options: QueryOptions| {
+            let path = Query::with_options(&arg, options).find().unwrap().path;
+            assert!(path.is_relative());
+
+            fs::canonicalize(path).unwrap()
+        };
+
+        assert_eq!(find(QueryOptions::new()), root.join("kn/src"));
+        assert_eq!(
+            find(QueryOptions::new().avoid(root.join("kn/src"))),
+            root.join("knot/src")
+        );
+        assert_eq!(
+            find(QueryOptions::new().avoid(relative(&root.join("kn/src")))),
+            root.join("knot/src")
+        );
+        assert_eq!(
+            find(QueryOptions::new().exclude(root.join("kn"))),
+            root.join("knot/src")
+        );
+
+        // The avoided path is still returned if it's the only match.
+        assert_eq!(
+            find(
+                QueryOptions::new()
+                    .avoid(root.join("kn/src"))
+                    .avoid(root.join("knot/./src"))
+            ),
+            root.join("kn/src")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_start_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        for path in ["real/kn/src", "real/knot/src"] {
+            fs::create_dir_all(root.join(path)).unwrap();
+        }
+        std::os::unix::fs::symlink(root.join("real"), root.join("link"))
+            .unwrap();
+        let find = |start_dir: &str, options: QueryOptions| {
+            Query::with_options(
+                "kn/=src",
+                options.start_dir(root.join(start_dir)),
+            )
+            .find()
+            .unwrap()
+            .path
+        };
+
+        // The logical path, like `$OLDPWD`, and the physical one, like the
+        // current dir, are equal.
+        assert_eq!(
+            find("link", QueryOptions::new().avoid(root.join("real/kn/src"))),
+            root.join("link/knot/src")
+        );
+        assert_eq!(
+            find("real", QueryOptions::new().avoid(root.join("link/kn/src"))),
+            root.join("real/knot/src")
+        );
+        assert_eq!(
+            find(
+                "link/../link",
+                QueryOptions::new().exclude(root.join("real/kn"))
+            ),
+            root.join("link/../link/knot/src")
+        );
+        assert_eq!(
+            find(
+                "link",
+                QueryOptions::new().exclude(
+                    Pattern::new(&format!("{}/real/k?", root.display()))
+                        .unwrap()
+                )
+            ),
+            root.join("link/knot/src")
+        );
+    }
 }
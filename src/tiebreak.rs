@@ -0,0 +1,245 @@
+//! Tiebreakers.
+
+use std::{
+    cmp::Ordering,
+    ffi::{OsStr, OsString},
+    path::{Component, Path},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use alphanumeric_sort::compare_os_str;
+use serde_derive::Deserialize;
+
+use crate::{
+    error::Error,
+    listing::{Lister, Metadata},
+};
+
+/// A way of ordering found paths with equal
+/// [`Congruence`](crate::abbr::Congruence)'s.
+///
+/// Tiebreakers are applied in the order in which they are configured.
+/// [`Alphanumeric`](Tiebreak::Alphanumeric) is always applied last, so that
+/// the order is deterministic. The times are read with the query's
+/// [`Lister`](Lister).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Tiebreak {
+    /// The most recently modified dir first.
+    Mtime,
+
+    /// The most recently accessed dir first.
+    Atime,
+
+    /// The dir with the fewest components first, after resolving `.` and `..`
+    /// components. Symlinks are not resolved, so a symlink's depth is the
+    /// depth of the path the abbreviation matched.
+    Depth,
+
+    /// The dir with the shortest name first.
+    NameLength,
+
+    /// Dir names ordered with
+    /// [`alphanumeric_sort::compare_os_str`](alphanumeric_sort::compare_os_str).
+    Alphanumeric,
+}
+
+const MTIME_TIEBREAK: &str = "mtime";
+const ATIME_TIEBREAK: &str = "atime";
+const DEPTH_TIEBREAK: &str = "depth";
+const NAME_LENGTH_TIEBREAK: &str = "name-length";
+const ALPHANUMERIC_TIEBREAK: &str = "alphanumeric";
+
+impl FromStr for Tiebreak {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            MTIME_TIEBREAK => Ok(Self::Mtime),
+            ATIME_TIEBREAK => Ok(Self::Atime),
+            DEPTH_TIEBREAK => Ok(Self::Depth),
+            NAME_LENGTH_TIEBREAK => Ok(Self::NameLength),
+            ALPHANUMERIC_TIEBREAK => Ok(Self::Alphanumeric),
+            _ => Err(Error::InvalidTiebreak(name.to_string())),
+        }
+    }
+}
+
+/// A value computed by a [`Tiebreak`](Tiebreak) for a single path.
+#[derive(Debug)]
+pub(crate) enum Key {
+    Time(Option<SystemTime>),
+    Count(usize),
+    Name(OsString),
+}
+
+impl Tiebreak {
+    /// Parses a comma-separated list of tiebreakers.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, Error> {
+        list.split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    /// Checks if the tiebreaker needs the dir's metadata.
+    fn reads_metadata(&self) -> bool {
+        matches!(self, Self::Mtime | Self::Atime)
+    }
+
+    /// Computes the tiebreaker's key for a path.
+    fn key(&self, path: &Path, file_name: &OsStr, metadata: &Metadata) -> Key {
+        match self {
+            Self::Mtime => Key::Time(metadata.modified),
+            Self::Atime => Key::Time(metadata.accessed),
+            Self::Depth => Key::Count(logical_depth(path)),
+            Self::NameLength =>
+                Key::Count(file_name.to_string_lossy().chars().count()),
+            Self::Alphanumeric => Key::Name(file_name.to_os_string()),
+        }
+    }
+}
+
+/// Counts the path's components after resolving `.` and `..` lexically.
+fn logical_depth(path: &Path) -> usize {
+    path.components()
+        .fold(0, |depth, component| match component {
+            Component::CurDir => depth,
+            Component::ParentDir => depth.saturating_sub(1),
+            _ => depth + 1,
+        })
+}
+
+/// Computes the keys of every tiebreaker for a path, followed by the key of
+/// [`Alphanumeric`](Tiebreak::Alphanumeric). The metadata is read with the
+/// lister at most once.
+pub(crate) fn keys(
+    tiebreaks: &[Tiebreak],
+    path: &Path,
+    file_name: &OsStr,
+    lister: &dyn Lister,
+) -> Vec<Key> {
+    let metadata = if tiebreaks.iter().any(Tiebreak::reads_metadata) {
+        lister.metadata(path)
+    } else {
+        Metadata::default()
+    };
+
+    tiebreaks
+        .iter()
+        .chain(Some(&Tiebreak::Alphanumeric))
+        .map(|tiebreak| tiebreak.key(path, file_name, &metadata))
+        .collect()
+}
+
+/// Compares keys computed by [`keys`](keys) with the same tiebreakers.
+pub(crate) fn compare_keys(keys_a: &[Key], keys_b: &[Key]) -> Ordering {
+    keys_a
+        .iter()
+        .zip(keys_b)
+        .map(|pair| match pair {
+            // The most recent first.
+            (Key::Time(time_a), Key::Time(time_b)) => time_b.cmp(time_a),
+            (Key::Count(count_a), Key::Count(count_b)) => count_a.cmp(count_b),
+            (Key::Name(name_a), Key::Name(name_b)) =>
+                compare_os_str(name_a, name_b),
+            _ => Ordering::Equal,
+        })
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_tiebreaks() {
+        assert_variant!(
+            Tiebreak::parse_list("mtime,atime,depth,name-length,alphanumeric")
+                .as_deref(),
+            Ok([
+                Tiebreak::Mtime,
+                Tiebreak::Atime,
+                Tiebreak::Depth,
+                Tiebreak::NameLength,
+                Tiebreak::Alphanumeric
+            ])
+        );
+        assert_variant!(Tiebreak::parse_list("").as_deref(), Ok([]));
+        assert_variant!(
+            Tiebreak::parse_list("mtime,size"),
+            Err(Error::InvalidTiebreak(name)) if name == "size"
+        );
+    }
+
+    #[test]
+    fn test_compare_keys() {
+        use std::time::Duration;
+
+        let earlier = SystemTime::UNIX_EPOCH;
+        let later = earlier + Duration::from_secs(1);
+
+        let keys_a = [Key::Time(Some(later)), Key::Name("b".into())];
+        let keys_b = [Key::Time(Some(earlier)), Key::Name("a".into())];
+        assert_eq!(compare_keys(&keys_a, &keys_b), Ordering::Less);
+
+        let keys_a = [Key::Time(None), Key::Name("a".into())];
+        let keys_b = [Key::Time(Some(earlier)), Key::Name("b".into())];
+        assert_eq!(compare_keys(&keys_a, &keys_b), Ordering::Greater);
+
+        let keys_a = [Key::Count(2), Key::Name("x2".into())];
+        let keys_b = [Key::Count(2), Key::Name("x10".into())];
+        assert_eq!(compare_keys(&keys_a, &keys_b), Ordering::Less);
+    }
+
+    #[test]
+    fn test_keys() {
+        use crate::listing::MemoryTree;
+
+        /// Lists a tree in memory with a known mtime.
+        struct TimedLister(MemoryTree, SystemTime);
+
+        impl Lister for TimedLister {
+            fn list(&self, dir: &Path) -> Vec<crate::Entry> {
+                self.0.list(dir)
+            }
+
+            fn metadata(&self, _path: &Path) -> Metadata {
+                Metadata {
+                    modified: Some(self.1),
+                    accessed: None,
+                }
+            }
+        }
+
+        let path = Path::new("/nonexistent/some-dir");
+        let file_name = OsStr::new("some-dir");
+        let tiebreaks =
+            [Tiebreak::Mtime, Tiebreak::Atime, Tiebreak::NameLength];
+
+        let unknown_times =
+            keys(&tiebreaks, path, file_name, &MemoryTree::new());
+        assert_variant!(unknown_times.as_slice(), [
+            Key::Time(None),
+            Key::Time(None),
+            Key::Count(8),
+            Key::Name(name)
+        ] if name == "some-dir");
+
+        let mtime = SystemTime::UNIX_EPOCH;
+        let lister = TimedLister(MemoryTree::new(), mtime);
+        assert_variant!(
+            keys(&tiebreaks, path, file_name, &lister).as_slice(),
+            [Key::Time(Some(time)), Key::Time(None), ..] if *time == mtime
+        );
+    }
+
+    #[test]
+    fn test_logical_depth() {
+        assert_eq!(logical_depth(Path::new("/a/b")), 3);
+        assert_eq!(logical_depth(Path::new("/a/./b/../c/d")), 4);
+        assert_eq!(logical_depth(Path::new("/a/link/..")), 2);
+    }
+}
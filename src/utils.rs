@@ -1,26 +1,21 @@
 //! Utils.
 
-#[cfg(any(test, doc))]
-use std::{convert::AsRef, path::Path};
-
 /// Asserts that the expression matches the variant. Optionally returns a value.
 ///
 /// Inspired by [`std::matches`](https://doc.rust-lang.org/stable/std/macro.matches.html).
+/// Only available in tests.
 ///
 /// # Examples
 ///
-/// ```
-/// # fn main() -> Option<()> {
-/// use kn::Congruence::*;
+/// ```ignore
+/// use kn::{Abbr, Congruence::*};
 ///
 /// let abbr = Abbr::new_sanitized("abcjkl");
-/// let coeff_1 = assert_variant!(abbr.compare("abc_jkl"), Some(Subsequence(coeff)) => coeff);
-/// let coeff_2 = assert_variant!(abbr.compare("ab_cj_kl"), Some(Subsequence(coeff)) => coeff);
-/// assert!(coeff_1 < coeff_2);
-/// # Ok(())
-/// # }
+/// let n_gaps_1 = assert_variant!(abbr.compare("abc_jkl"), Some(Boundary(n_gaps)) => n_gaps);
+/// let n_gaps_2 = assert_variant!(abbr.compare("ab_cj_kl"), Some(Boundary(n_gaps)) => n_gaps);
+/// assert!(n_gaps_1 < n_gaps_2);
 /// ```
-#[cfg(any(test, doc))]
+#[cfg(test)]
 #[macro_export]
 macro_rules! assert_variant {
     ($expression_in:expr , $( $pattern:pat )|+ $( if $guard: expr )? $( => $expression_out:expr )? ) => {
@@ -38,12 +33,3 @@ macro_rules! assert_variant {
         }
     };
 }
-
-/// Shorthand for `AsRef<Path>::as_ref(&x)`.
-#[cfg(any(test, doc))]
-pub fn as_path<P>(path: &P) -> &Path
-where
-    P: AsRef<Path> + ?Sized,
-{
-    path.as_ref()
-}